@@ -31,6 +31,7 @@ impl Drop for DIR
 pub struct dirent
 {
     pub d_name: CString,
+    pub d_type: u8,
 }
 
 /// Call fdopendir(3) with the given arguments.
@@ -71,5 +72,8 @@ pub fn readdir(dirp: &mut DIR) -> io::Result<Option<dirent>>
     let d_name = unsafe { CStr::from_ptr((*dirent).d_name.as_ptr()) };
     let d_name = d_name.to_owned();
 
-    Ok(Some(dirent{d_name}))
+    // SAFETY: dirent points to a valid dirent.
+    let d_type = unsafe { (*dirent).d_type };
+
+    Ok(Some(dirent{d_name, d_type}))
 }
@@ -37,3 +37,78 @@ pub fn openat(
     // SAFETY: fd is a new, open file descriptor.
     Ok(unsafe { OwnedFd::from_raw_fd(fd) })
 }
+
+/// Call posix_fadvise(2) with the given arguments.
+///
+/// posix_fadvise(2) returns the error number instead of setting `errno`.
+/// The wrapper function translates it into an error as usual.
+pub fn posix_fadvise(
+    fd:     BorrowedFd,
+    offset: libc::off_t,
+    len:    libc::off_t,
+    advice: libc::c_int,
+) -> io::Result<()>
+{
+    // SAFETY: This is always safe.
+    let result = unsafe {
+        libc::posix_fadvise(fd.as_raw_fd(), offset, len, advice)
+    };
+
+    if result != 0 {
+        return Err(io::Error::from_raw_os_error(result));
+    }
+
+    Ok(())
+}
+
+/// Call posix_fallocate(3) with the given arguments.
+///
+/// posix_fallocate(3) returns the error number instead of setting `errno`.
+/// The wrapper function translates it into an error as usual.
+pub fn posix_fallocate(fd: BorrowedFd, offset: libc::off_t, len: libc::off_t)
+    -> io::Result<()>
+{
+    // SAFETY: This is always safe.
+    let result = unsafe { libc::posix_fallocate(fd.as_raw_fd(), offset, len) };
+
+    if result != 0 {
+        return Err(io::Error::from_raw_os_error(result));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests
+{
+    use {
+        super::*,
+        crate::{AT_EMPTY_PATH, O_RDWR, O_TMPFILE, POSIX_FADV_DONTNEED, cstr, fstatat},
+        std::{io::ErrorKind::InvalidInput, os::unix::io::AsFd},
+    };
+
+    #[test]
+    fn posix_fadvise_dontneed()
+    {
+        let file = open(cstr!(b"/tmp"), O_RDWR | O_TMPFILE, 0o644).unwrap();
+        posix_fadvise(file.as_fd(), 0, 0, POSIX_FADV_DONTNEED).unwrap();
+    }
+
+    #[test]
+    fn posix_fadvise_invalid()
+    {
+        // The error is reported through the return value, not errno.
+        let file = open(cstr!(b"/tmp"), O_RDWR | O_TMPFILE, 0o644).unwrap();
+        let err = posix_fadvise(file.as_fd(), 0, 0, -1).unwrap_err();
+        assert_eq!(err.kind(), InvalidInput);
+    }
+
+    #[test]
+    fn posix_fallocate_size()
+    {
+        let file = open(cstr!(b"/tmp"), O_RDWR | O_TMPFILE, 0o644).unwrap();
+        posix_fallocate(file.as_fd(), 0, 4096).unwrap();
+        let statbuf = fstatat(Some(file.as_fd()), cstr!(b""), AT_EMPTY_PATH);
+        assert_eq!(statbuf.unwrap().st_size, 4096);
+    }
+}
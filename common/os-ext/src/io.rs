@@ -1,10 +1,13 @@
 //! Working with file descriptors.
 
-use std::{
-    ffi::CString,
-    io,
-    mem::ManuallyDrop,
-    os::unix::io::{AsRawFd, BorrowedFd, FromRawFd, OwnedFd},
+use {
+    crate::{AT_EMPTY_PATH, cstr, fstatat, readlink},
+    std::{
+        ffi::CString,
+        io::{self, ErrorKind::NotFound},
+        mem::ManuallyDrop,
+        os::unix::io::{AsRawFd, BorrowedFd, FromRawFd, OwnedFd},
+    },
 };
 
 /// Return the path to the `/proc/self/fd` entry for the file descriptor.
@@ -14,11 +17,43 @@ pub fn magic_link(fd: BorrowedFd) -> CString
         .expect("RawFd as Display should not write nul")
 }
 
+/// Return the path to the file referred to by the file descriptor.
+///
+/// This reads the [`magic_link`] for the file descriptor,
+/// so `/proc` must be mounted for this function to work.
+/// If it is not, the returned error says so,
+/// rather than being an unexplained [`NotFound`] error.
+/// If the file was unlinked, the path has ` (deleted)` appended to it.
+pub fn fd_path(fd: BorrowedFd) -> io::Result<CString>
+{
+    match readlink(&magic_link(fd)) {
+        Err(err) if err.kind() == NotFound => {
+            let proc_fd = cstr!(b"/proc/self/fd");
+            match fstatat(None, proc_fd, 0) {
+                Err(err) if err.kind() == NotFound => {
+                    let message = "/proc/self/fd is unavailable; is /proc mounted?";
+                    Err(io::Error::new(NotFound, message))
+                },
+                _ => Err(err),
+            }
+        },
+        result => result,
+    }
+}
+
 /// Extra methods for [`BorrowedFd`].
 pub trait BorrowedFdExt: Sized
 {
     /// Analogous to [`OwnedFd::try_clone`].
+    ///
+    /// The new file descriptor has `FD_CLOEXEC` set.
     fn try_to_owned(self) -> io::Result<OwnedFd>;
+
+    /// Whether both file descriptors refer to the same file.
+    ///
+    /// Files are compared by their device and inode numbers,
+    /// so hard links to the same file are the same file.
+    fn is_same_file(&self, other: BorrowedFd) -> io::Result<bool>;
 }
 
 impl BorrowedFdExt for BorrowedFd<'_>
@@ -29,4 +64,72 @@ impl BorrowedFdExt for BorrowedFd<'_>
         let owned = unsafe { OwnedFd::from_raw_fd(self.as_raw_fd()) };
         ManuallyDrop::new(owned).try_clone()
     }
+
+    fn is_same_file(&self, other: BorrowedFd) -> io::Result<bool>
+    {
+        let this  = fstatat(Some(*self), cstr!(b""), AT_EMPTY_PATH)?;
+        let other = fstatat(Some(other), cstr!(b""), AT_EMPTY_PATH)?;
+        Ok((this.st_dev, this.st_ino) == (other.st_dev, other.st_ino))
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use {
+        super::*,
+        crate::{
+            O_CREAT, O_PATH, O_WRONLY,
+            cstring, linkat, openat, temp_dir::TempDir,
+        },
+        std::os::unix::io::AsFd,
+    };
+
+    #[test]
+    fn fd_path_temp_file()
+    {
+        let temp_dir = TempDir::new(cstring!(b"/tmp/snowflake-test-XXXXXX")).unwrap();
+        let dir = Some(temp_dir.as_fd());
+        let file = openat(dir, cstr!(b"a"), O_CREAT | O_WRONLY, 0o644).unwrap();
+
+        let expected = [temp_dir.path().to_bytes(), b"/a"].concat();
+        assert_eq!(fd_path(file.as_fd()).unwrap().as_bytes(), expected);
+    }
+
+    #[test]
+    fn try_to_owned()
+    {
+        let temp_dir = TempDir::new(cstring!(b"/tmp/snowflake-test-XXXXXX")).unwrap();
+        let dir = Some(temp_dir.as_fd());
+        let file = openat(dir, cstr!(b"a"), O_CREAT | O_WRONLY, 0o644).unwrap();
+
+        let owned = file.as_fd().try_to_owned().unwrap();
+        assert_ne!(owned.as_raw_fd(), file.as_raw_fd());
+        assert!(owned.as_fd().is_same_file(file.as_fd()).unwrap());
+
+        // SAFETY: F_GETFD only reads the flags of a valid file descriptor.
+        let flags = unsafe { libc::fcntl(owned.as_raw_fd(), libc::F_GETFD) };
+        assert_eq!(flags & libc::FD_CLOEXEC, libc::FD_CLOEXEC);
+    }
+
+    #[test]
+    fn is_same_file()
+    {
+        let temp_dir = TempDir::new(cstring!(b"/tmp/snowflake-test-XXXXXX")).unwrap();
+        let dir = Some(temp_dir.as_fd());
+        openat(dir, cstr!(b"a"), O_CREAT | O_WRONLY, 0o644).unwrap();
+        openat(dir, cstr!(b"b"), O_CREAT | O_WRONLY, 0o644).unwrap();
+        linkat(dir, cstr!(b"a"), dir, cstr!(b"c"), 0).unwrap();
+
+        let a = openat(dir, cstr!(b"a"), O_PATH, 0).unwrap();
+        let b = openat(dir, cstr!(b"b"), O_PATH, 0).unwrap();
+        let c = openat(dir, cstr!(b"c"), O_PATH, 0).unwrap();
+        assert!(!a.as_fd().is_same_file(b.as_fd()).unwrap());
+        assert!(a.as_fd().is_same_file(c.as_fd()).unwrap());
+
+        // The hard links have different paths but are the same file.
+        let a_path = fd_path(a.as_fd()).unwrap();
+        let c_path = fd_path(c.as_fd()).unwrap();
+        assert_ne!(a_path, c_path);
+    }
 }
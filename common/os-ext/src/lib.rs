@@ -28,29 +28,39 @@
 //!
 //! [`Result`]: `std::io::Result`
 
+#![feature(concat_bytes)]
 #![feature(io_safety)]
 #![warn(missing_docs)]
 
 pub use {
-    self::{dirent_::*, fcntl::*, stdio::*, stdlib::*, sys_stat::*, unistd::*},
+    self::{
+        dirent_::*, fcntl::*, stdio::*, stdlib::*,
+        sys_prctl::*, sys_stat::*, unistd::*,
+    },
     libc::{
-        AT_SYMLINK_FOLLOW, AT_SYMLINK_NOFOLLOW,
-        O_CREAT, O_DIRECTORY, O_NOFOLLOW, O_PATH,
+        AT_EMPTY_PATH, AT_REMOVEDIR, AT_SYMLINK_FOLLOW, AT_SYMLINK_NOFOLLOW,
+        DT_DIR, DT_UNKNOWN,
+        O_CREAT, O_DIRECTORY, O_EXCL, O_NOFOLLOW, O_PATH,
         O_RDONLY, O_RDWR, O_TMPFILE, O_WRONLY,
+        POSIX_FADV_DONTNEED, POSIX_FADV_NOREUSE, POSIX_FADV_NORMAL,
+        POSIX_FADV_RANDOM, POSIX_FADV_SEQUENTIAL, POSIX_FADV_WILLNEED,
         RENAME_NOREPLACE,
-        S_IFDIR, S_IFIFO, S_IFLNK, S_IFMT, S_IFREG, S_IXUSR,
+        S_IFDIR, S_IFIFO, S_IFLNK, S_IFMT, S_IFREG, S_IFSOCK, S_IXUSR,
         S_ISGID, S_ISUID, S_ISVTX,
-        gid_t, uid_t,
+        STATX_BASIC_STATS, STATX_BTIME, STATX_MNT_ID,
+        gid_t, pid_t, uid_t,
     },
 };
 
 pub mod cstr;
 pub mod io;
+pub mod temp_dir;
 
 mod dirent_;
 mod fcntl;
 mod stdio;
 mod stdlib;
+mod sys_prctl;
 mod sys_stat;
 mod unistd;
 
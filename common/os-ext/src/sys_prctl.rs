@@ -0,0 +1,66 @@
+use std::io;
+
+/// Call prctl(2) with `PR_SET_NO_NEW_PRIVS`.
+///
+/// This function is async-signal-safe,
+/// so it may be called between clone and execve.
+pub fn prctl_set_no_new_privs() -> io::Result<()>
+{
+    // SAFETY: This is always safe.
+    let result = unsafe {
+        libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1 as libc::c_ulong, 0, 0, 0)
+    };
+
+    if result == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+/// Call prctl(2) with `PR_SET_PDEATHSIG`.
+///
+/// Note that the signal is sent when the parent *thread* terminates,
+/// not when the parent process terminates.
+/// This function is async-signal-safe,
+/// so it may be called between clone and execve.
+pub fn prctl_set_pdeathsig(sig: libc::c_int) -> io::Result<()>
+{
+    // SAFETY: This is always safe.
+    let result = unsafe {
+        libc::prctl(libc::PR_SET_PDEATHSIG, sig as libc::c_ulong, 0, 0, 0)
+    };
+
+    if result == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+/// Call prctl(2) with `PR_SET_SECCOMP` and `SECCOMP_MODE_FILTER`.
+///
+/// This function is async-signal-safe,
+/// so it may be called between clone and execve.
+/// Unless the caller has `CAP_SYS_ADMIN`,
+/// [`prctl_set_no_new_privs`] must be called first.
+pub fn prctl_set_seccomp_filter(filter: &[libc::sock_filter])
+    -> io::Result<()>
+{
+    let len = u16::try_from(filter.len())
+        .map_err(|_| io::Error::from_raw_os_error(libc::EINVAL))?;
+
+    let fprog = libc::sock_fprog{len, filter: filter.as_ptr() as *mut _};
+
+    // SAFETY: The kernel does not write to the filter.
+    let result = unsafe {
+        libc::prctl(libc::PR_SET_SECCOMP, libc::SECCOMP_MODE_FILTER,
+                    &fprog as *const libc::sock_fprog, 0, 0)
+    };
+
+    if result == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
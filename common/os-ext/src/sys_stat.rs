@@ -8,6 +8,30 @@ use {
     },
 };
 
+/// Call fchmodat(2) with the given arguments.
+///
+/// If `dirfd` is [`None`], `AT_FDCWD` is passed.
+pub fn fchmodat(
+    dirfd: Option<BorrowedFd>,
+    pathname: &CStr,
+    mode: libc::mode_t,
+    flags: libc::c_int,
+) -> io::Result<()>
+{
+    let dirfd = dirfd.map(|fd| fd.as_raw_fd()).unwrap_or(libc::AT_FDCWD);
+
+    // SAFETY: path is NUL-terminated.
+    let result = unsafe {
+        libc::fchmodat(dirfd, pathname.as_ptr(), mode, flags)
+    };
+
+    if result == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
 /// Call fstatat(2) with the given arguments.
 ///
 /// If `dirfd` is [`None`], `AT_FDCWD` is passed.
@@ -91,3 +115,134 @@ pub fn mknodat(
 
     Ok(())
 }
+
+/// Call statx(2) with the given arguments.
+///
+/// If `dirfd` is [`None`], `AT_FDCWD` is passed.
+///
+/// Unlike the glibc wrapper, this calls the system call directly.
+/// On kernels that predate statx(2), it fails with `ENOSYS`
+/// instead of silently falling back to fstatat(2),
+/// which would leave fields such as `stx_btime` and `stx_mnt_id` unset.
+/// Use the accessors on [`Statx`] to find out which fields were filled in.
+pub fn statx(
+    dirfd: Option<BorrowedFd>,
+    pathname: &CStr,
+    flags: libc::c_int,
+    mask: libc::c_uint,
+) -> io::Result<Statx>
+{
+    let dirfd = dirfd.map(|fd| fd.as_raw_fd()).unwrap_or(libc::AT_FDCWD);
+
+    let mut statxbuf = MaybeUninit::<libc::statx>::uninit();
+
+    // SAFETY: path is NUL-terminated.
+    let result = unsafe {
+        libc::syscall(
+            libc::SYS_statx,
+            dirfd,
+            pathname.as_ptr(),
+            flags,
+            mask,
+            statxbuf.as_mut_ptr(),
+        )
+    };
+
+    if result == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // SAFETY: statx initialized statxbuf.
+    Ok(Statx(unsafe { statxbuf.assume_init() }))
+}
+
+/// Result of [`statx`].
+///
+/// Fields that statx(2) may leave unset are only accessible
+/// through accessors that check `stx_mask`.
+#[derive(Clone, Copy)]
+pub struct Statx(libc::statx);
+
+impl Statx
+{
+    /// Which fields were filled in, as a set of `STATX_*` flags.
+    pub fn mask(&self) -> u32
+    {
+        self.0.stx_mask
+    }
+
+    /// The file type and mode, if filled in.
+    pub fn mode(&self) -> Option<libc::mode_t>
+    {
+        let mask = libc::STATX_TYPE | libc::STATX_MODE;
+        self.masked(mask, self.0.stx_mode as libc::mode_t)
+    }
+
+    /// The birth time of the file, if filled in.
+    ///
+    /// Not every file system records birth times.
+    pub fn btime(&self) -> Option<libc::statx_timestamp>
+    {
+        self.masked(libc::STATX_BTIME, self.0.stx_btime)
+    }
+
+    /// The ID of the mount containing the file, if filled in.
+    ///
+    /// This is the same ID as in `/proc/self/mountinfo`.
+    pub fn mnt_id(&self) -> Option<u64>
+    {
+        self.masked(libc::STATX_MNT_ID, self.0.stx_mnt_id)
+    }
+
+    /// Return the value if all fields in `mask` were filled in.
+    fn masked<T>(&self, mask: u32, value: T) -> Option<T>
+    {
+        if self.0.stx_mask & mask == mask { Some(value) } else { None }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use {
+        super::*,
+        crate::{
+            S_IFMT, S_IFREG, STATX_BTIME, STATX_MNT_ID,
+            cstr, cstring, cstr::CStrExt, temp_dir::TempDir,
+        },
+        std::time::{SystemTime, UNIX_EPOCH},
+    };
+
+    #[test]
+    fn statx_btime()
+    {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+
+        // Create a fresh file.
+        let temp_dir = TempDir::new(cstring!(b"/tmp/snowflake-test-XXXXXX")).unwrap();
+        let path = temp_dir.path().join(cstr!(b"file"));
+        mknod(&path, S_IFREG | 0o644, 0).unwrap();
+
+        // Not every file system records birth times.
+        // If it does, the birth time must be recent.
+        let statxbuf = statx(None, &path, 0, STATX_BTIME).unwrap();
+        assert_eq!(statxbuf.mode().unwrap() & S_IFMT, S_IFREG);
+        assert_eq!(statxbuf.btime().is_some(), statxbuf.mask() & STATX_BTIME != 0);
+        if let Some(btime) = statxbuf.btime() {
+            let btime = btime.tv_sec as u64;
+            assert!(btime + 1 >= now.as_secs(), "{btime} {now:?}");
+        }
+    }
+
+    #[test]
+    fn statx_mnt_id()
+    {
+        // Files on the same mount have the same mount ID.
+        let a = statx(None, cstr!(b"/proc/self"), 0, STATX_MNT_ID).unwrap();
+        let b = statx(None, cstr!(b"/proc/self/fd"), 0, STATX_MNT_ID).unwrap();
+        assert_eq!(a.mnt_id().is_some(), a.mask() & STATX_MNT_ID != 0);
+        if let (Some(a), Some(b)) = (a.mnt_id(), b.mnt_id()) {
+            assert_eq!(a, b);
+        }
+    }
+}
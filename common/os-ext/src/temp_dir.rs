@@ -0,0 +1,159 @@
+//! Temporary directories.
+
+use {
+    crate::{
+        AT_REMOVEDIR, AT_SYMLINK_NOFOLLOW, DT_DIR, DT_UNKNOWN,
+        O_DIRECTORY, O_NOFOLLOW, O_PATH, O_RDONLY, S_IFDIR, S_IFMT,
+        fdopendir, fstatat, mkdtemp, open, openat, readdir, unlinkat,
+    },
+    scope_exit::ScopeExit,
+    std::{
+        ffi::{CStr, CString},
+        io,
+        os::unix::io::{AsFd, BorrowedFd, OwnedFd},
+    },
+};
+
+/// Directory that is recursively removed when dropped.
+///
+/// Errors during removal are ignored,
+/// as there is no way to report them from [`Drop::drop`].
+pub struct TempDir
+{
+    path: CString,
+    dir: OwnedFd,
+    remove: ScopeExit<Box<dyn FnOnce() + Send + Sync>>,
+}
+
+impl TempDir
+{
+    /// Create a directory using [`mkdtemp`].
+    ///
+    /// The directory is opened with `O_DIRECTORY | O_PATH`.
+    pub fn new(template: CString) -> io::Result<Self>
+    {
+        let path = mkdtemp(template)?;
+        let remove_path = path.clone();
+        let remove = ScopeExit::new(Box::new(move || {
+            let _ = remove_all(None, &remove_path);
+        }) as Box<dyn FnOnce() + Send + Sync>);
+        let dir = open(&path, O_DIRECTORY | O_PATH, 0)?;
+        Ok(Self{path, dir, remove})
+    }
+
+    /// The path to the directory.
+    pub fn path(&self) -> &CStr
+    {
+        &self.path
+    }
+
+    /// Return the path to the directory without removing it.
+    pub fn into_path(self) -> CString
+    {
+        let Self{path, dir, remove} = self;
+        drop(dir);
+        let _ = remove.into_inner();
+        path
+    }
+}
+
+impl AsFd for TempDir
+{
+    fn as_fd(&self) -> BorrowedFd
+    {
+        self.dir.as_fd()
+    }
+}
+
+/// Remove a file, or a directory and everything in it.
+///
+/// Symbolic links are removed, not followed.
+pub fn remove_all(dirfd: Option<BorrowedFd>, pathname: &CStr) -> io::Result<()>
+{
+    let statbuf = fstatat(dirfd, pathname, AT_SYMLINK_NOFOLLOW)?;
+    let is_dir = statbuf.st_mode & S_IFMT == S_IFDIR;
+    remove_all_typed(dirfd, pathname, is_dir)
+}
+
+/// Like [`remove_all`], but the caller says whether the file is a directory.
+fn remove_all_typed(dirfd: Option<BorrowedFd>, pathname: &CStr, is_dir: bool)
+    -> io::Result<()>
+{
+    if !is_dir {
+        return unlinkat(dirfd, pathname, 0);
+    }
+
+    let dir = openat(dirfd, pathname, O_DIRECTORY | O_NOFOLLOW | O_RDONLY, 0)?;
+    let mut entries = fdopendir(dir.try_clone()?)?;
+    while let Some(entry) = readdir(&mut entries)? {
+        let name = entry.d_name.to_bytes();
+        if name == b"." || name == b".." {
+            continue;
+        }
+
+        // Not every file system fills in d_type, so fall back to fstatat.
+        let dir = Some(dir.as_fd());
+        match entry.d_type {
+            DT_UNKNOWN => remove_all(dir, &entry.d_name)?,
+            d_type => remove_all_typed(dir, &entry.d_name, d_type == DT_DIR)?,
+        }
+    }
+
+    unlinkat(dirfd, pathname, AT_REMOVEDIR)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use {
+        super::*,
+        crate::{O_CREAT, O_WRONLY, cstr, cstring, mkdirat, symlinkat},
+        std::io::ErrorKind::NotFound,
+    };
+
+    #[test]
+    fn drop_removes_directory()
+    {
+        let temp_dir = TempDir::new(cstring!(b"/tmp/snowflake-test-XXXXXX")).unwrap();
+        let path = temp_dir.path().to_owned();
+
+        // Populate the directory with files of various types.
+        let dir = Some(temp_dir.as_fd());
+        mkdirat(dir, cstr!(b"a"), 0o755).unwrap();
+        mkdirat(dir, cstr!(b"a/b"), 0o755).unwrap();
+        openat(dir, cstr!(b"a/b/c"), O_CREAT | O_WRONLY, 0o644).unwrap();
+        symlinkat(cstr!(b"/tmp"), dir, cstr!(b"a/d")).unwrap();
+
+        drop(temp_dir);
+
+        let err = fstatat(None, &path, AT_SYMLINK_NOFOLLOW).unwrap_err();
+        assert_eq!(err.kind(), NotFound);
+    }
+
+    #[test]
+    fn remove_all_symlink_to_directory()
+    {
+        let target = TempDir::new(cstring!(b"/tmp/snowflake-test-XXXXXX")).unwrap();
+        openat(Some(target.as_fd()), cstr!(b"f"), O_CREAT | O_WRONLY, 0o644).unwrap();
+
+        // The symbolic link is removed, not the directory it points to.
+        let temp_dir = TempDir::new(cstring!(b"/tmp/snowflake-test-XXXXXX")).unwrap();
+        let dir = Some(temp_dir.as_fd());
+        symlinkat(target.path(), dir, cstr!(b"l")).unwrap();
+        remove_all(dir, cstr!(b"l")).unwrap();
+
+        let err = fstatat(dir, cstr!(b"l"), AT_SYMLINK_NOFOLLOW).unwrap_err();
+        assert_eq!(err.kind(), NotFound);
+        fstatat(Some(target.as_fd()), cstr!(b"f"), AT_SYMLINK_NOFOLLOW).unwrap();
+    }
+
+    #[test]
+    fn into_path_keeps_directory()
+    {
+        let temp_dir = TempDir::new(cstring!(b"/tmp/snowflake-test-XXXXXX")).unwrap();
+        let path = temp_dir.into_path();
+
+        fstatat(None, &path, AT_SYMLINK_NOFOLLOW).unwrap();
+        unlinkat(None, &path, AT_REMOVEDIR).unwrap();
+    }
+}
@@ -1,5 +1,5 @@
 use {
-    crate::{gid_t, uid_t},
+    crate::{gid_t, pid_t, uid_t},
     std::{
         ffi::{CStr, CString},
         io,
@@ -14,6 +14,33 @@ pub fn getgid() -> gid_t
     unsafe { libc::getgid() }
 }
 
+/// Call getpgid(2) with the given arguments.
+pub fn getpgid(pid: pid_t) -> io::Result<pid_t>
+{
+    // SAFETY: This is always safe.
+    let pgid = unsafe { libc::getpgid(pid) };
+
+    if pgid == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(pgid)
+}
+
+/// Call getpid(2).
+pub fn getpid() -> pid_t
+{
+    // SAFETY: This is always safe.
+    unsafe { libc::getpid() }
+}
+
+/// Call gettid(2).
+pub fn gettid() -> pid_t
+{
+    // SAFETY: This is always safe.
+    unsafe { libc::gettid() }
+}
+
 /// Call getuid(2).
 pub fn getuid() -> uid_t
 {
@@ -71,6 +98,22 @@ pub fn pipe2(flags: libc::c_int) -> io::Result<(OwnedFd, OwnedFd)>
     ))
 }
 
+/// Call pidfd_open(2) with the given arguments.
+///
+/// The kernel always creates the file descriptor with `FD_CLOEXEC` set.
+pub fn pidfd_open(pid: pid_t, flags: libc::c_uint) -> io::Result<OwnedFd>
+{
+    // SAFETY: This is always safe.
+    let result = unsafe { libc::syscall(libc::SYS_pidfd_open, pid, flags) };
+
+    if result == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // SAFETY: This file descriptor is fresh.
+    Ok(unsafe { OwnedFd::from_raw_fd(result as libc::c_int) })
+}
+
 /// Equivalent to [`readlinkat`] with [`None`] passed for `dirfd`.
 pub fn readlink(pathname: &CStr) -> io::Result<CString>
 {
@@ -125,6 +168,19 @@ pub fn readlinkat(dirfd: Option<BorrowedFd>, pathname: &CStr)
     }
 }
 
+/// Call setpgid(2) with the given arguments.
+pub fn setpgid(pid: pid_t, pgid: pid_t) -> io::Result<()>
+{
+    // SAFETY: This is always safe.
+    let result = unsafe { libc::setpgid(pid, pgid) };
+
+    if result == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
 /// Equivalent to [`symlinkat`] with [`None`] passed for `newdirfd`.
 pub fn symlink(target: &CStr, linkpath: &CStr) -> io::Result<()>
 {
@@ -154,11 +210,84 @@ pub fn symlinkat(
     Ok(())
 }
 
+/// Equivalent to [`unlinkat`] with [`None`] passed for `dirfd`
+/// and zero passed for `flags`.
+pub fn unlink(pathname: &CStr) -> io::Result<()>
+{
+    unlinkat(None, pathname, 0)
+}
+
+/// Call unlinkat(2) with the given arguments.
+///
+/// If `dirfd` is [`None`], `AT_FDCWD` is passed.
+/// Pass `AT_REMOVEDIR` in `flags` to remove an empty directory.
+/// A missing file is reported as `ENOENT` like any other error;
+/// it is up to the caller to decide whether that is a problem.
+pub fn unlinkat(
+    dirfd: Option<BorrowedFd>,
+    pathname: &CStr,
+    flags: libc::c_int,
+) -> io::Result<()>
+{
+    let dirfd = dirfd.map(|fd| fd.as_raw_fd()).unwrap_or(libc::AT_FDCWD);
+
+    // SAFETY: pathname is NUL-terminated.
+    let result = unsafe { libc::unlinkat(dirfd, pathname.as_ptr(), flags) };
+
+    if result == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
 
 #[cfg(test)]
 mod tests
 {
-    use super::*;
+    use {
+        super::*,
+        crate::{
+            AT_REMOVEDIR, AT_SYMLINK_NOFOLLOW, O_CREAT, O_WRONLY,
+            cstr, cstring, fstatat, mkdirat, openat, temp_dir::TempDir,
+        },
+        std::{io::ErrorKind::NotFound, os::unix::io::AsFd},
+    };
+
+    #[test]
+    fn getpid_gettid()
+    {
+        let pid = getpid();
+        assert_eq!(pid as u32, std::process::id());
+        assert_eq!(getpgid(0).unwrap(), getpgid(pid).unwrap());
+
+        // Other threads have their own thread IDs.
+        let tid = std::thread::spawn(gettid).join().unwrap();
+        assert_ne!(tid, gettid());
+    }
+
+    #[test]
+    fn pidfd_open_self()
+    {
+        let pidfd = pidfd_open(getpid(), 0).unwrap();
+
+        // The pidfd becomes readable only once the process terminates.
+        let mut pollfd = libc::pollfd{
+            fd: pidfd.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        // SAFETY: pollfd is a valid array of one element.
+        let result = unsafe { libc::poll(&mut pollfd, 1, 0) };
+        assert_eq!(result, 0);
+    }
+
+    #[test]
+    fn setpgid_bad_pgid()
+    {
+        let err = setpgid(0, -1).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::EINVAL));
+    }
 
     #[test]
     fn readlinkat_loop()
@@ -170,4 +299,39 @@ mod tests
             assert_eq!(actual.as_bytes(), expected.as_bytes());
         }
     }
+
+    #[test]
+    fn unlinkat_file()
+    {
+        let temp_dir = TempDir::new(cstring!(b"/tmp/snowflake-test-XXXXXX")).unwrap();
+        let dir = Some(temp_dir.as_fd());
+
+        openat(dir, cstr!(b"file"), O_CREAT | O_WRONLY, 0o644).unwrap();
+        unlinkat(dir, cstr!(b"file"), 0).unwrap();
+
+        let err = fstatat(dir, cstr!(b"file"), AT_SYMLINK_NOFOLLOW).unwrap_err();
+        assert_eq!(err.kind(), NotFound);
+
+        // Removing it again must report ENOENT.
+        let err = unlinkat(dir, cstr!(b"file"), 0).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::ENOENT));
+    }
+
+    #[test]
+    fn unlinkat_removedir()
+    {
+        let temp_dir = TempDir::new(cstring!(b"/tmp/snowflake-test-XXXXXX")).unwrap();
+        let dir = Some(temp_dir.as_fd());
+
+        mkdirat(dir, cstr!(b"dir"), 0o755).unwrap();
+
+        // Without AT_REMOVEDIR, directories cannot be removed.
+        let err = unlinkat(dir, cstr!(b"dir"), 0).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::EISDIR));
+
+        unlinkat(dir, cstr!(b"dir"), AT_REMOVEDIR).unwrap();
+
+        let err = fstatat(dir, cstr!(b"dir"), AT_SYMLINK_NOFOLLOW).unwrap_err();
+        assert_eq!(err.kind(), NotFound);
+    }
 }
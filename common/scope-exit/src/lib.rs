@@ -24,6 +24,14 @@ impl<F> ScopeExit<F>
     {
         Self{f: ManuallyDrop::new(f)}
     }
+
+    /// Disarm the scope guard and return `f` without calling it.
+    pub fn into_inner(self) -> F
+    {
+        let mut this = ManuallyDrop::new(self);
+        // SAFETY: this is not dropped, so this.f will not be used anymore.
+        unsafe { ManuallyDrop::take(&mut this.f) }
+    }
 }
 
 impl<F> Drop for ScopeExit<F>
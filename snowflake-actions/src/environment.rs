@@ -0,0 +1,131 @@
+use {std::ffi::CString, thiserror::Error};
+
+/// Builder for [`RunCommand::environment`].
+///
+/// [`minimal`][`Self::minimal`] is the recommended starting point
+/// for actions that are meant to be reproducible.
+/// Inheriting variables from the host instead would leak information
+/// such as the terminal type or the location of the SSH agent,
+/// which has no business affecting the outputs of an action.
+///
+/// [`RunCommand::environment`]: `crate::RunCommand::environment`
+#[derive(Clone, Debug, Default)]
+pub struct Environment
+{
+    variables: Vec<CString>,
+}
+
+/// Returned when an environment variable could not be set.
+///
+/// See [`Environment::with`] for the restrictions on variables.
+#[derive(Debug, Error)]
+#[error("Environment variable name is empty or contains `=` or nul, \
+         or value contains nul")]
+pub struct EnvironmentError;
+
+impl Environment
+{
+    /// Create an environment with no variables.
+    pub fn new() -> Self
+    {
+        Self::default()
+    }
+
+    /// Create an environment with a small deterministic set of variables.
+    ///
+    /// These are, in order:
+    /// `PATH=/bin:/usr/bin`, `HOME=/build`, `TZ=UTC`, and `LC_ALL=C`.
+    /// Note that inside the container, `/bin` contains only `sh`
+    /// and `/usr/bin` contains only `env`, so other programs
+    /// must be referred to by absolute path or `PATH` must be replaced.
+    pub fn minimal() -> Self
+    {
+        [
+            ("PATH", "/bin:/usr/bin"),
+            ("HOME", "/build"),
+            ("TZ", "UTC"),
+            ("LC_ALL", "C"),
+        ]
+        .into_iter()
+        .try_fold(Self::new(), |env, (name, value)| env.with(name, value))
+        .expect("Minimal environment variables should be valid")
+    }
+
+    /// Set a variable.
+    ///
+    /// If the variable was already set, its value is replaced in place.
+    /// Otherwise, the variable is appended.
+    ///
+    /// Returns an error if the name is empty or contains `=` or nul,
+    /// or if the value contains nul.
+    pub fn with(mut self, name: impl AsRef<[u8]>, value: impl AsRef<[u8]>)
+        -> Result<Self, EnvironmentError>
+    {
+        let (name, value) = (name.as_ref(), value.as_ref());
+        if name.is_empty() || name.contains(&b'=') {
+            return Err(EnvironmentError);
+        }
+
+        let variable = CString::new([name, b"=", value].concat())
+            .map_err(|_| EnvironmentError)?;
+
+        let existing = self.variables.iter_mut()
+            .find(|v| matches!(v.as_bytes().strip_prefix(name), Some([b'=', ..])));
+        match existing {
+            Some(existing) => *existing = variable,
+            None => self.variables.push(variable),
+        }
+
+        Ok(self)
+    }
+
+    /// The variables, in the form expected by execve(2).
+    pub fn into_vec(self) -> Vec<CString>
+    {
+        self.variables
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use {super::*, os_ext::cstring};
+
+    #[test]
+    fn minimal()
+    {
+        assert_eq!(
+            Environment::minimal().into_vec(),
+            [
+                cstring!(b"PATH=/bin:/usr/bin"),
+                cstring!(b"HOME=/build"),
+                cstring!(b"TZ=UTC"),
+                cstring!(b"LC_ALL=C"),
+            ],
+        );
+    }
+
+    #[test]
+    fn with_replaces()
+    {
+        let environment =
+            Environment::minimal()
+            .with("TZ", "Europe/Amsterdam").unwrap()
+            .with("T", "x").unwrap()
+            .into_vec();
+        assert_eq!(environment[2], cstring!(b"TZ=Europe/Amsterdam"));
+        assert_eq!(environment[4], cstring!(b"T=x"));
+        assert_eq!(environment.len(), 5);
+    }
+
+    #[test]
+    fn with_invalid()
+    {
+        let with = |name: &[u8], value: &[u8]| Environment::new().with(name, value);
+        assert!(with(b"", b"x").is_err());
+        assert!(with(b"A=B", b"x").is_err());
+        assert!(with(b"A\0", b"x").is_err());
+        assert!(with(b"A", b"x\0").is_err());
+        assert!(with(b"A", b"").is_ok());
+    }
+}
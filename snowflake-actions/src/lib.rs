@@ -5,15 +5,22 @@
 #![doc(html_logo_url = "/snowflake-manual/_static/logo.svg")]
 #![feature(assert_matches)]
 #![feature(concat_bytes)]
-#![feature(exit_status_error)]
 #![feature(io_safety)]
 #![feature(let_else)]
 #![feature(panic_always_abort)]
 #![feature(type_ascription)]
 #![warn(missing_docs)]
 
-pub use self::{create_symbolic_link::*, run_command::*, write_regular_file::*};
+pub use self::{
+    create_symbolic_link::*,
+    environment::*,
+    run_command::*,
+    seccomp::*,
+    write_regular_file::*,
+};
 
 mod create_symbolic_link;
+mod environment;
 mod run_command;
+mod seccomp;
 mod write_regular_file;
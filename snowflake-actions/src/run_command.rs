@@ -1,34 +1,46 @@
 use {
+    crate::SeccompPolicy,
     anyhow::Context,
     os_ext::{
-        AT_SYMLINK_NOFOLLOW,
+        AT_EMPTY_PATH, AT_SYMLINK_NOFOLLOW,
+        O_CREAT, O_EXCL, O_WRONLY,
         S_IFDIR, S_IFLNK, S_IFMT, S_IFREG,
-        cstr, cstr_cow, fstatat, getgid, getuid, mkdirat,
-        mknodat, pipe2, readlink, readlinkat, symlinkat,
+        cstr, cstr_cow, fchmodat, fstatat, getgid, getpid, getuid, mkdirat,
+        mknodat, openat, pidfd_open, pipe2,
+        prctl_set_no_new_privs, prctl_set_pdeathsig,
+        prctl_set_seccomp_filter, readlinkat, setpgid, symlinkat,
         cstr::CStrExt,
-        io::{BorrowedFdExt, magic_link},
+        io::{BorrowedFdExt, fd_path},
     },
     regex::bytes::Regex,
     scope_exit::ScopeExit,
     snowflake_core::action::{
-        Action, Error, InputPath, Outputs, Perform, Success,
+        Action, Error, InputFileType, InputPath, Outputs, Perform, Success,
+        input_file_type,
         Result as AResult,
     },
     snowflake_util::{basename::Basename, hash::{Blake3, Hash}},
     std::{
         borrow::Cow,
+        collections::HashSet,
         ffi::{CStr, CString},
-        fs::File,
-        io::{self, BufRead, BufReader, Read, Seek},
+        fs::{self, File},
+        io::{self, BufRead, BufReader, ErrorKind::NotFound, Read, Seek, Write},
+        iter::once,
         mem::{forget, size_of_val, zeroed},
         os::unix::{
-            io::{AsRawFd, BorrowedFd, FromRawFd, OwnedFd},
+            ffi::OsStrExt,
+            fs::FileExt,
+            io::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd},
             process::ExitStatusExt,
         },
         panic::always_abort,
-        process::ExitStatus,
-        ptr::{addr_of, addr_of_mut, null, null_mut},
-        time::Duration,
+        path::{Component, Path, PathBuf},
+        process::{self, ExitStatus},
+        ptr::{addr_of, addr_of_mut, null},
+        sync::atomic::{AtomicU64, Ordering::SeqCst},
+        thread,
+        time::{Duration, Instant},
     },
 };
 
@@ -44,19 +56,52 @@ pub struct RunCommand
     /// Absolute path to the program to run.
     pub program: CString,
 
+    /// Working directory of the program, relative to `/build`.
+    ///
+    /// If [`None`], the program runs in `/build` itself.
+    /// The directory is created if it does not exist.
+    /// It may also be (inside) a directory input,
+    /// so that the program can run inside a source tree.
+    /// The path may not contain `..` components
+    /// and may not be absolute.
+    pub working_directory: Option<PathBuf>,
+
     /// Arguments to the program.
     ///
     /// This should include the zeroth argument,
     /// which is normally equal to [`program`][`Self::program`].
     pub arguments: Vec<CString>,
 
+    /// Zeroth argument to the program, overriding that in `arguments`.
+    ///
+    /// Multi-call programs such as BusyBox behave differently
+    /// depending on the zeroth argument they are passed.
+    /// If set, this replaces the first element of
+    /// [`arguments`][`Self::arguments`], or is prepended if it is empty.
+    /// [`program`][`Self::program`] is still what gets executed.
+    pub argv0: Option<CString>,
+
     /// The environment variables to the program.
     ///
     /// This specifies the *exact* environment to the program.
     /// No extra environment variables are set by the
     /// [`perform`][`RunCommand::perform`] method.
+    /// [`Environment::minimal`][`crate::Environment::minimal`]
+    /// is a good starting point.
     pub environment: Vec<CString>,
 
+    /// What to feed the program on its standard input.
+    ///
+    /// If [`None`], the standard input is closed.
+    pub stdin: Option<Vec<u8>>,
+
+    /// Index of the output that receives the program's standard output.
+    ///
+    /// If [`None`], the standard output goes to the build log.
+    /// Otherwise, the output file is created before the program runs,
+    /// and only the standard error goes to the build log.
+    pub stdout_output: Option<usize>,
+
     /// How much time the program may spend.
     ///
     /// If the program spends more time than this,
@@ -67,6 +112,175 @@ pub struct RunCommand
     ///
     /// If [`None`], no warnings are assumed to have been emitted.
     pub warnings: Option<Regex>,
+
+    /// How many lines at the end of the build log to include in
+    /// [`Error::Command`] when the program terminates unsuccessfully.
+    ///
+    /// The build log is still written in full regardless.
+    /// [`DEFAULT_LOG_TAIL_LINES`][`Self::DEFAULT_LOG_TAIL_LINES`]
+    /// is a reasonable choice for most commands.
+    pub log_tail_lines: usize,
+
+    /// Size limit in bytes of the build log.
+    ///
+    /// If the build log grows larger than this,
+    /// the program is killed and the action fails.
+    /// If [`None`], the build log may grow without bounds.
+    /// The size of the build log is checked periodically,
+    /// so it may briefly exceed the limit before the program is killed.
+    pub log_limit: Option<u64>,
+
+    /// System calls the program may not make.
+    ///
+    /// If [`None`], no system calls are filtered.
+    /// Otherwise, denied system calls fail with `EPERM`;
+    /// see [`SeccompPolicy`] for details.
+    /// A policy that denies no system calls is treated like [`None`].
+    pub seccomp: Option<SeccompPolicy>,
+
+    /// Hostname of the container.
+    ///
+    /// If [`None`], the hostname is
+    /// [`DEFAULT_HOSTNAME`][`Self::DEFAULT_HOSTNAME`].
+    /// Either way the hostname is deterministic,
+    /// so that programs embedding it are reproducible.
+    pub hostname: Option<CString>,
+
+    /// How user IDs inside the container map to those outside it.
+    ///
+    /// If empty, user 0 inside the container maps to
+    /// the user performing the action, and no other users are mapped.
+    /// The mappings may not overlap, inside or outside the container.
+    pub uid_map: Vec<IdMapping>,
+
+    /// How group IDs inside the container map to those outside it.
+    ///
+    /// Like [`uid_map`][`Self::uid_map`], but for groups.
+    pub gid_map: Vec<IdMapping>,
+
+    /// Whether to create `/etc/passwd` and `/etc/group`.
+    ///
+    /// If set, these files describe a single user and group named `build`,
+    /// with the IDs that the command runs as inside the container.
+    /// This makes functions such as getpwuid(3) work.
+    /// If not set, `/etc` does not exist inside the container.
+    pub provide_passwd: bool,
+
+    /// Size limit in bytes of the container's `/tmp` directory.
+    ///
+    /// If [`None`], `/tmp` is a directory in the scratch directory.
+    /// Otherwise, a fresh tmpfs of the given size is mounted at `/tmp`.
+    pub tmp_size: Option<u64>,
+
+    /// Resource limits for the command.
+    ///
+    /// If [`None`], the command is not limited beyond the limits
+    /// that already apply to the process performing the action.
+    pub cgroup_limits: Option<CgroupLimits>,
+}
+
+/// Resource limits enforced using cgroup v2.
+///
+/// The command is run in a transient cgroup created inside [`parent`],
+/// which is removed again when the action finishes.
+/// This requires the unified cgroup v2 hierarchy and a kernel with
+/// support for `CLONE_INTO_CGROUP` (Linux 5.7 or later).
+/// [`parent`] must be delegated to the user performing the action,
+/// and the controllers for the configured limits (`memory`, `cpu`)
+/// must be enabled in its `cgroup.subtree_control` file.
+/// Because of the "no internal processes" rule,
+/// [`parent`] must not itself contain any processes.
+///
+/// [`parent`]: `Self::parent`
+pub struct CgroupLimits
+{
+    /// Absolute path to the cgroup in which to create the transient cgroup.
+    pub parent: PathBuf,
+
+    /// Maximum memory usage in bytes, written to `memory.max`.
+    ///
+    /// Swap is disabled by writing zero to `memory.swap.max`,
+    /// so that exceeding this limit triggers the OOM killer.
+    /// If [`None`], memory usage is not limited.
+    pub memory_max: Option<u64>,
+
+    /// Maximum CPU bandwidth, written to `cpu.max`.
+    ///
+    /// The command may use the first number of microseconds
+    /// of CPU time during every period of the second number of microseconds.
+    /// If [`None`], CPU bandwidth is not limited.
+    pub cpu_max: Option<(u64, u64)>,
+}
+
+/// Maps a range of IDs inside the container to IDs outside it.
+///
+/// See user_namespaces(7) for how mappings are interpreted.
+/// Without privileges, only the ID of the user or group
+/// performing the action can be mapped, with a count of one.
+#[derive(Clone, Copy, Debug)]
+pub struct IdMapping
+{
+    /// First ID of the range inside the container.
+    pub inside: u32,
+
+    /// First ID of the range outside the container.
+    pub outside: u32,
+
+    /// Number of IDs in the range.
+    pub count: u32,
+}
+
+impl RunCommand
+{
+    /// Suggested value for [`log_tail_lines`][`Self::log_tail_lines`].
+    pub const DEFAULT_LOG_TAIL_LINES: usize = 20;
+
+    /// Hostname used when [`hostname`][`Self::hostname`] is [`None`].
+    pub const DEFAULT_HOSTNAME: &'static str = "snowflake";
+
+    /// Timeout used by [`new`][`Self::new`].
+    pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+
+    /// Create an action that runs a program with the given arguments.
+    ///
+    /// The action has no inputs or outputs and an empty environment.
+    /// Every other field is set to its most restrictive value,
+    /// or to its suggested default where there is one.
+    /// Use struct update syntax to override fields:
+    ///
+    /// ```
+    /// # use {snowflake_actions::RunCommand, std::ffi::CString};
+    /// let sh = CString::new("/bin/sh").unwrap();
+    /// let action = RunCommand{
+    ///     provide_passwd: true,
+    ///     ..RunCommand::new(sh.clone(), vec![sh])
+    /// };
+    /// ```
+    pub fn new(program: CString, arguments: Vec<CString>) -> Self
+    {
+        Self{
+            inputs: vec![],
+            outputs: Outputs::Outputs(vec![]),
+            program,
+            working_directory: None,
+            arguments,
+            argv0: None,
+            environment: vec![],
+            stdin: None,
+            stdout_output: None,
+            timeout: Self::DEFAULT_TIMEOUT,
+            warnings: None,
+            log_tail_lines: Self::DEFAULT_LOG_TAIL_LINES,
+            log_limit: None,
+            seccomp: None,
+            hostname: None,
+            uid_map: vec![],
+            gid_map: vec![],
+            provide_passwd: false,
+            tmp_size: None,
+            cgroup_limits: None,
+        }
+    }
 }
 
 impl Action for RunCommand
@@ -93,8 +307,10 @@ impl Action for RunCommand
         const OUTPUTS_TYPE_OUTPUTS: u8 = 0;
         const OUTPUTS_TYPE_LINT:    u8 = 1;
 
-        let Self{inputs, outputs, program, arguments,
-                 environment, timeout, warnings} = self;
+        let Self{inputs, outputs, program, working_directory, arguments,
+                 argv0, environment, stdin, stdout_output, timeout, warnings,
+                 log_tail_lines, log_limit, seccomp, hostname, uid_map,
+                 gid_map, provide_passwd, tmp_size, cgroup_limits} = self;
 
         debug_assert_eq!(input_hashes.len(), inputs.len());
 
@@ -119,17 +335,36 @@ impl Action for RunCommand
         }
 
         h.put_cstr(program);
+        h.put_opt(working_directory.as_deref(), Blake3::put_path);
         h.put_slice(arguments, |h, a| h.put_cstr(a));
-        h.put_slice(environment, |h, e| h.put_cstr(e));
-
-        // The timeout cannot affect the output of the action,
-        // so there is no need to include it in the hash.
-        let _ = timeout;
-
-        h.put_bool(warnings.is_some());
-        if let Some(warnings) = warnings {
-            h.put_str(warnings.as_str());
-        }
+        h.put_opt(argv0.as_deref(), Blake3::put_cstr);
+        h.put_map(environment, environment_variable_name, |h, e| h.put_cstr(e));
+        h.put_opt(stdin.as_deref(), Blake3::put_bytes);
+        h.put_opt(stdout_output.as_ref(), |h, &i| h.put_usize(i));
+
+        h.put_opt(warnings.as_ref(), |h, w| h.put_str(w.as_str()));
+
+        h.put_opt(effective_seccomp(seccomp), |h, s| {
+            let denied: Vec<_> = s.denied().collect();
+            h.put_slice(&denied, |h, &d| h.put_u64(d as u64))
+        });
+
+        // Hash the effective hostname, so that specifying
+        // the default hostname explicitly does not change the hash.
+        h.put_bytes(hostname.as_deref().map_or(
+            Self::DEFAULT_HOSTNAME.as_bytes(),
+            CStr::to_bytes,
+        ));
+
+        put_id_map(&mut h, uid_map);
+        put_id_map(&mut h, gid_map);
+        h.put_bool(*provide_passwd);
+
+        // The timeout, log limit, size of /tmp, and resource limits
+        // can only cause the action to fail, and the log tail only
+        // affects error reporting. None of them affect the outputs,
+        // so there is no need to include them in the hash.
+        let _ = (timeout, log_tail_lines, log_limit, tmp_size, cgroup_limits);
 
         h.finalize()
     }
@@ -143,8 +378,9 @@ fn perform_run_command(
 {
     // Unpack the arguments into convenient variables.
     let Perform{build_log, scratch} = perform;
-    let RunCommand{inputs, outputs, program, arguments,
-                   environment, timeout, warnings} = action;
+    let RunCommand{inputs, outputs, working_directory, arguments, argv0,
+                   stdout_output, warnings, uid_map, gid_map, provide_passwd,
+                   tmp_size, cgroup_limits, ..} = action;
 
     // Mounting must happen in the child process,
     // so we collect all the mount calls in here.
@@ -156,20 +392,67 @@ fn perform_run_command(
     populate_root_directory(*scratch)?;
     populate_dev_directory(*scratch, &mut mounts)?;
     install_blessed_programs(*scratch)?;
+    if *provide_passwd {
+        let uid = inside_id(uid_map, getuid());
+        let gid = inside_id(gid_map, getgid());
+        install_passwd(*scratch, uid, gid)?;
+    }
     repair_root_mount(&mut mounts);
     mount_proc(&mut mounts);
+    mount_tmp(*tmp_size, &mut mounts);
     mount_nix_store(&mut mounts);
     mount_inputs(*scratch, inputs, input_paths, &mut mounts)?;
-    run_command(*build_log, &scratch_path, program,
-                arguments, environment, *timeout,
-                mounts)?;
+    let working_directory =
+        working_directory_components(working_directory.as_deref())
+        .with_context(|| "Validate working directory")?;
+    let arguments = override_argv0(arguments, argv0.as_deref());
+    let uid_map = id_map_contents(uid_map, getuid())                            .with_context(|| "Validate user ID mappings")?;
+    let gid_map = id_map_contents(gid_map, getgid())                            .with_context(|| "Validate group ID mappings")?;
     let output_paths = output_paths(outputs);
+    let stdout =
+        create_stdout_output(*scratch, &output_paths, *stdout_output)
+        .with_context(|| "Create output for standard output")?;
+
+    // The transient cgroup must outlive the child,
+    // so it is removed only after run_command returns.
+    let cgroup_path = cgroup_limits.as_ref().map(create_cgroup).transpose()?;
+    let _cgroup_guard = ScopeExit::new(|| {
+        if let Some(cgroup_path) = &cgroup_path {
+            let _ = fs::remove_dir(cgroup_path);
+        }
+    });
+    let cgroup =
+        cgroup_limits.as_ref().zip(cgroup_path.as_ref())
+        .map(|(limits, path)| configure_cgroup(path, limits))
+        .transpose()?;
+
+    let prepared = Prepared{
+        scratch_path: &scratch_path,
+        working_directory: &working_directory,
+        arguments: &arguments,
+        stdout: stdout.as_ref().map(AsFd::as_fd),
+        uid_map: &uid_map,
+        gid_map: &gid_map,
+        cgroup: cgroup.as_ref().map(AsFd::as_fd),
+    };
+    run_command(*build_log, action, prepared, mounts)?;
+    validate_outputs(*scratch, outputs, &output_paths)?;
     let warnings = find_warnings(*build_log, warnings.as_ref())?;
 
     // Summarize the result.
     Ok(Success{output_paths, warnings})
 }
 
+/// Replace the zeroth argument, if an override is given.
+fn override_argv0<'a>(arguments: &'a [CString], argv0: Option<&CStr>)
+    -> Cow<'a, [CString]>
+{
+    let Some(argv0) = argv0 else { return Cow::Borrowed(arguments) };
+    let rest = arguments.get(1 ..).unwrap_or(&[]);
+    let arguments = once(argv0.to_owned()).chain(rest.iter().cloned());
+    Cow::Owned(arguments.collect())
+}
+
 /// Arguments to mount.
 #[derive(Default)]
 struct Mount<'a>
@@ -220,7 +503,12 @@ fn populate_root_directory(scratch: BorrowedFd) -> Result<(), Error>
     mk(cstr!(b"root"),      0o755)?;
     mk(cstr!(b"usr"),       0o755)?;
     mk(cstr!(b"usr/bin"),   0o755)?;
+    mk(cstr!(b"tmp"),       0o755)?;
     mk(cstr!(b"build"),     0o755)?;
+
+    // mkdirat is subject to the umask, so set the sticky bit separately.
+    fchmodat(Some(scratch), cstr!(b"tmp"), 0o1777, 0)                           .with_context(|| "Change mode of \"tmp\" inside container")?;
+
     Ok(())
 }
 
@@ -279,6 +567,24 @@ fn install_blessed_programs(scratch: BorrowedFd) -> Result<(), Error>
     Ok(())
 }
 
+/// Create the container's `/etc/passwd` and `/etc/group` files,
+/// each describing a single entry named `build`.
+fn install_passwd(scratch: BorrowedFd, uid: u32, gid: u32) -> Result<(), Error>
+{
+    mkdirat(Some(scratch), cstr!(b"etc"), 0o755)                                .with_context(|| "Create \"etc\" inside container")?;
+
+    let mk = |path: &CStr, contents: String| -> Result<(), Error> {
+        let flags = O_CREAT | O_EXCL | O_WRONLY;
+        let file = openat(Some(scratch), path, flags, 0o644)                    .with_context(|| format!("Create {path:?} inside container"))?;
+        File::from(file).write_all(contents.as_bytes())                         .with_context(|| format!("Write {path:?} inside container"))?;
+        Ok(())
+    };
+    mk(cstr!(b"etc/passwd"), format!("build:x:{uid}:{gid}:build:/build:/bin/sh\n"))?;
+    mk(cstr!(b"etc/group"),  format!("build:x:{gid}:\n"))?;
+
+    Ok(())
+}
+
 /// Prevent mount events from propagating out of the container.
 ///
 /// `/` is usually mounted with `MS_SHARED`, but we want `MS_PRIVATE`.
@@ -307,6 +613,23 @@ fn mount_proc(mounts: &mut Vec<Mount>)
     mounts.push(mount);
 }
 
+/// Mount a tmpfs at the container's path `/tmp`, if a size is given.
+fn mount_tmp(tmp_size: Option<u64>, mounts: &mut Vec<Mount>)
+{
+    let Some(tmp_size) = tmp_size
+        else { return };
+
+    let data = format!("size={tmp_size},mode=1777");
+    let mount = Mount{
+        source: cstr_cow!(b"tmpfs"),
+        target: cstr_cow!(b"tmp"),
+        filesystemtype: cstr_cow!(b"tmpfs"),
+        mountflags: libc::MS_NODEV | libc::MS_NOSUID,
+        data: CString::new(data).unwrap().into(),
+    };
+    mounts.push(mount);
+}
+
 /// Mount the Nix store at the container's path `/nix/store`.
 fn mount_nix_store(mounts: &mut Vec<Mount>)
 {
@@ -326,6 +649,15 @@ fn mount_inputs(
 {
     debug_assert_eq!(input_paths.len(), inputs.len());
 
+    // Inputs with the same basename would be mounted onto the same target.
+    let mut seen = HashSet::new();
+    for input_basename in inputs {
+        if !seen.insert(input_basename) {
+            let message = format!("Duplicate input basename {input_basename:?}");
+            return Err(Error::from(anyhow::Error::msg(message)));
+        }
+    }
+
     for (input_basename, input_path) in inputs.iter().zip(input_paths) {
         mount_input(scratch, input_basename, input_path, mounts)
             .with_context(|| format!("Mount input at {input_basename:?}"))?;
@@ -353,34 +685,188 @@ fn mount_input(
     let target = cstr!(b"build").join(input_basename);
 
     // How to mount the input depends on what type of file it is.
-    let statbuf = fstatat(None, &input_path, AT_SYMLINK_NOFOLLOW)               .with_context(|| "Find file type of input")?;
-    match statbuf.st_mode & S_IFMT {
-        S_IFREG => {
+    let file_type = input_file_type(None, &input_path)                          .with_context(|| "Find file type of input")?;
+    match file_type {
+        InputFileType::Regular => {
             // If it's a regular file, the target must be a regular file.
             mknodat(Some(scratch), &target, S_IFREG | 0o644, 0)                 .with_context(|| "Create mount target")?;
             let mount = Mount::rdonly_bind_mount(input_path.into(), target.into());
             mounts.extend(mount);
         },
-        S_IFDIR => {
+        InputFileType::Directory => {
             // If it's a directory, the target must be a directory.
             mkdirat(Some(scratch), &target, 0o755)                              .with_context(|| "Create mount target")?;
             let mount = Mount::rdonly_bind_mount(input_path.into(), target.into());
             mounts.extend(mount);
         },
-        S_IFLNK => {
+        InputFileType::Symlink => {
             // If it's a symbolic link, we're fucked as they can't be mounted.
             // Copy the symbolic link instead (should be fast; they're small).
+            // Only the symbolic link itself is copied, not what it points to,
+            // as the input is the symbolic link. This also avoids symlink loops.
             let symlink_target = readlinkat(None, &input_path)                  .with_context(|| "Find target of symbolic link")?;
             symlinkat(&symlink_target, Some(scratch), &target)                  .with_context(|| "Create copy of symbolic link")?;
         },
-        _ =>
-            unreachable!("Input has been hashed by the driver,
-                          so it can only be of a supported type"),
+        InputFileType::Other =>
+            // The driver cannot hash such an input, so it should not
+            // have passed it, but a bug in the driver is no reason to abort.
+            anyhow::bail!("Input is of unsupported file type"),
     }
 
     Ok(())
 }
 
+/// Create a transient cgroup in which to run the command.
+fn create_cgroup(limits: &CgroupLimits) -> Result<PathBuf, Error>
+{
+    static NEXT_CGROUP: AtomicU64 = AtomicU64::new(0);
+
+    let name = format!("snowflake-{}-{}", process::id(),
+                       NEXT_CGROUP.fetch_add(1, SeqCst));
+    let path = limits.parent.join(name);
+    fs::create_dir(&path)                                                       .with_context(|| format!("Create cgroup {path:?}"))?;
+
+    Ok(path)
+}
+
+/// Write the limits to the transient cgroup
+/// and open it for use with `CLONE_INTO_CGROUP`.
+fn configure_cgroup(path: &Path, limits: &CgroupLimits)
+    -> Result<OwnedFd, Error>
+{
+    let write = |file: &str, value: &str| fs::write(path.join(file), value)     .with_context(|| format!("Write {value:?} to {file:?} in cgroup {path:?}"));
+
+    if let Some(memory_max) = limits.memory_max {
+        write("memory.max", &memory_max.to_string())?;
+
+        // memory.swap.max only exists if swap accounting is enabled,
+        // in which case there is no swap to disable either.
+        match fs::write(path.join("memory.swap.max"), "0") {
+            Err(err) if err.kind() == NotFound => (),
+            result => result                                                    .with_context(|| format!("Disable swap in cgroup {path:?}"))?,
+        }
+    }
+
+    if let Some((quota, period)) = limits.cpu_max {
+        write("cpu.max", &format!("{quota} {period}"))?;
+    }
+
+    let cgroup = File::open(path)                                               .with_context(|| format!("Open cgroup {path:?}"))?;
+
+    Ok(cgroup.into())
+}
+
+/// Format ID mappings for writing to `/proc/self/uid_map` or `gid_map`.
+///
+/// If there are no mappings, ID 0 inside the container
+/// is mapped to `default_outside`.
+fn id_map_contents(map: &[IdMapping], default_outside: u32)
+    -> anyhow::Result<String>
+{
+    if map.is_empty() {
+        return Ok(format!("0 {default_outside} 1\n"));
+    }
+
+    let range = |start: u32, count: u32| start as u64 .. start as u64 + count as u64;
+    let overlap = |a: &std::ops::Range<u64>, b: &std::ops::Range<u64>|
+        a.start < b.end && b.start < a.end;
+
+    for (i, a) in map.iter().enumerate() {
+        if a.count == 0 {
+            anyhow::bail!("ID mapping {a:?} is empty");
+        }
+        for b in &map[i + 1 ..] {
+            if overlap(&range(a.inside, a.count), &range(b.inside, b.count)) ||
+                overlap(&range(a.outside, a.count), &range(b.outside, b.count)) {
+                anyhow::bail!("ID mappings {a:?} and {b:?} overlap");
+            }
+        }
+    }
+
+    let contents =
+        map.iter()
+        .map(|m| format!("{} {} {}\n", m.inside, m.outside, m.count))
+        .collect();
+
+    Ok(contents)
+}
+
+/// The ID inside the container that `outside` maps to.
+///
+/// This agrees with the mappings written by [`id_map_contents`].
+/// IDs that are not mapped appear as the overflow ID 65534.
+fn inside_id(map: &[IdMapping], outside: u32) -> u32
+{
+    if map.is_empty() {
+        return 0;
+    }
+
+    map.iter()
+        .find(|m| outside >= m.outside && outside - m.outside < m.count)
+        .map_or(65534, |m| m.inside + (outside - m.outside))
+}
+
+/// The part of an environment variable before the first `=`.
+///
+/// Used for hashing the environment independent of the order of variables.
+/// Duplicate variables are still sensitive to order,
+/// as the order determines which one takes effect.
+fn environment_variable_name(variable: &CString) -> &[u8]
+{
+    let variable = variable.as_bytes();
+    variable.split(|&b| b == b'=').next().unwrap_or(variable)
+}
+
+/// The seccomp policy to install, if any.
+///
+/// A policy that denies nothing is not installed,
+/// so that it behaves and hashes the same as no policy.
+fn effective_seccomp(seccomp: &Option<SeccompPolicy>) -> Option<&SeccompPolicy>
+{
+    seccomp.as_ref().filter(|s| s.denied().next().is_some())
+}
+
+/// Used for hashing ID mappings.
+/// Only the shape of the mapping is included, as the command can observe it.
+/// The outside IDs are not, as they depend on the user performing the action.
+/// An empty map is hashed like the default mapping it stands for.
+fn put_id_map<'a>(h: &'a mut Blake3, map: &[IdMapping]) -> &'a mut Blake3
+{
+    let shape: Vec<(u32, u32)> =
+        if map.is_empty() {
+            vec![(0, 1)]
+        } else {
+            map.iter().map(|m| (m.inside, m.count)).collect()
+        };
+    h.put_slice(&shape, |h, &(inside, count)| {
+        h.put_u64(inside as u64).put_u64(count as u64)
+    })
+}
+
+/// Split the working directory into components,
+/// which the child creates and changes into one by one.
+fn working_directory_components(working_directory: Option<&Path>)
+    -> anyhow::Result<Vec<CString>>
+{
+    let Some(working_directory) = working_directory
+        else { return Ok(Vec::new()) };
+
+    let mut components = Vec::new();
+    for component in working_directory.components() {
+        match component {
+            Component::Normal(component) =>
+                components.push(CString::new(component.as_bytes())?),
+            Component::CurDir =>
+                continue,
+            Component::Prefix(..) | Component::RootDir | Component::ParentDir =>
+                anyhow::bail!("Working directory {working_directory:?} \
+                               escapes the build directory"),
+        }
+    }
+
+    Ok(components)
+}
+
 /// Compute the scratch-relative path at which each output is created.
 fn output_paths(outputs: &Outputs<Vec<Basename<CString>>>) -> Vec<CString>
 {
@@ -390,6 +876,54 @@ fn output_paths(outputs: &Outputs<Vec<Basename<CString>>>) -> Vec<CString>
         .get()
 }
 
+/// Create the output that receives the standard output, if any.
+fn create_stdout_output(
+    scratch: BorrowedFd,
+    output_paths: &[CString],
+    stdout_output: Option<usize>,
+) -> anyhow::Result<Option<OwnedFd>>
+{
+    let Some(index) = stdout_output
+        else { return Ok(None) };
+
+    let Some(output_path) = output_paths.get(index)
+        else { anyhow::bail!("There is no output with index {index}") };
+
+    let flags = O_CREAT | O_EXCL | O_WRONLY;
+    let file = openat(Some(scratch), output_path, flags, 0o644)?;
+
+    Ok(Some(file))
+}
+
+/// Check that every output exists and is of a supported file type.
+fn validate_outputs(
+    scratch: BorrowedFd,
+    outputs: &Outputs<Vec<Basename<CString>>>,
+    output_paths: &[CString],
+) -> Result<(), Error>
+{
+    let Outputs::Outputs(outputs) = outputs
+        else { return Ok(()) };
+
+    debug_assert_eq!(output_paths.len(), outputs.len());
+
+    for (basename, output_path) in outputs.iter().zip(output_paths) {
+        let statbuf = fstatat(Some(scratch), output_path, AT_SYMLINK_NOFOLLOW);
+        let statbuf = match statbuf {
+            Err(err) if err.kind() == NotFound =>
+                return Err(Error::MissingOutput{basename: basename.clone()}),
+            result =>
+                result                                                          .with_context(|| format!("Find file type of output {basename:?}"))?,
+        };
+        match statbuf.st_mode & S_IFMT {
+            S_IFREG | S_IFDIR | S_IFLNK => (),
+            _ => return Err(Error::UnsupportedOutputType{basename: basename.clone()}),
+        }
+    }
+
+    Ok(())
+}
+
 /// Look for warnings in the build log.
 fn find_warnings(build_log: BorrowedFd, warnings: Option<&Regex>)
     -> Result<bool, Error>
@@ -427,38 +961,70 @@ fn find_warnings(build_log: BorrowedFd, warnings: Option<&Regex>)
 ///  2. `fchdir` doesn't work with mount namespaces for whichever reason.
 fn resolve_magic(fd: BorrowedFd) -> Result<CString, Error>
 {
-    readlink(&magic_link(fd))
+    fd_path(fd)
         .map_err(|err| Error::from(anyhow::Error::from(err)))
 }
 
+/// How often to check the size of the build log against the limit.
+const LOG_LIMIT_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Values computed from a [`RunCommand`] action
+/// while setting up the container.
+struct Prepared<'a>
+{
+    scratch_path: &'a CStr,
+    working_directory: &'a [CString],
+    arguments: &'a [CString],
+    stdout: Option<BorrowedFd<'a>>,
+    uid_map: &'a str,
+    gid_map: &'a str,
+    cgroup: Option<BorrowedFd<'a>>,
+}
+
 /// Run the command in the already set up container.
 fn run_command(
     build_log: BorrowedFd,
-    scratch_path: &CStr,
-    program: &CStr,
-    arguments: &[CString],
-    environment: &[CString],
-    timeout: Duration,
+    action: &RunCommand,
+    prepared: Prepared,
     // By value, to prevent accidentally adding
     // mounts *after* running the command. :)
     mounts: Vec<Mount>,
 ) -> Result<(), Error>
 {
-    // Prepare writes to /proc/self/gid_map and /proc/self/uid_map.
-    // These files map users and groups inside the container
-    // to users and groups outside the container.
+    // Unpack the arguments into convenient variables.
+    let RunCommand{program, environment, stdin, timeout, log_tail_lines,
+                   log_limit, seccomp, hostname, ..} = action;
+    let Prepared{scratch_path, working_directory, arguments, stdout,
+                 uid_map, gid_map, cgroup} = prepared;
+
+    // Prepare writes to /proc/self/setgroups, which is required
+    // for writing the ID mappings without privileges.
     let setgroups = "deny\n";
-    let uid_map = format!("0 {} 1\n", getuid());
-    let gid_map = format!("0 {} 1\n", getgid());
+
+    // Prepare the hostname, which is not nul-terminated.
+    let hostname = hostname.as_deref().map_or(
+        RunCommand::DEFAULT_HOSTNAME.as_bytes(),
+        CStr::to_bytes,
+    );
 
     // Prepare arguments to execve.
     let (execve_argv, _execve_argv) = prepare_argv_envp(arguments);
     let (execve_envp, _execve_envp) = prepare_argv_envp(environment);
 
+    // Compile the seccomp filter, which cannot be done after clone3.
+    let seccomp_filter = effective_seccomp(seccomp).map(SeccompPolicy::compile);
+
     // This pipe is used by the child to send pre-execve errors to the parent.
     // Since CLOEXEC is enabled, the parent knows execve has succeeded.
     let (pipe_r, pipe_w) = pipe2(0)                                             .with_context(|| "Create pipe for parent-child communication")?;
 
+    // This pidfd is used by the child to find out whether
+    // the parent died before the parent death signal was set up.
+    let parent_pidfd = pidfd_open(getpid(), 0)                                  .with_context(|| "Open pidfd of parent process")?;
+
+    // This pipe is used by the parent to feed the child its standard input.
+    let stdin_pipe = stdin.as_ref().map(|_| pipe2(0)).transpose()               .with_context(|| "Create pipe for standard input")?;
+
     // Zero-initialize this because we don't use most of its features.
     let mut cl_args = unsafe { zeroed::<clone_args>() };
 
@@ -479,6 +1045,13 @@ fn run_command(
     cl_args.flags |= libc::CLONE_PIDFD as u64;
     cl_args.pidfd = addr_of_mut!(pidfd) as u64;
 
+    // Start the child in the transient cgroup, if any.
+    // That way the limits apply before the child runs any code.
+    if let Some(cgroup) = cgroup {
+        cl_args.flags |= CLONE_INTO_CGROUP;
+        cl_args.cgroup = cgroup.as_raw_fd() as u64;
+    }
+
     // We don't actually care about the exit signal,
     // but if we don't set this then waitpid doesn't work.
     cl_args.exit_signal = libc::SIGCHLD as u64;
@@ -535,6 +1108,37 @@ fn run_command(
             }
         };
 
+        // If the parent thread dies, it can no longer enforce the timeout.
+        // Kill the child so it does not keep running unsupervised.
+        let pdeathsig = prctl_set_pdeathsig(libc::SIGKILL);
+        enforce("prctl PR_SET_PDEATHSIG", pdeathsig.is_ok());
+
+        // If the parent died before the above prctl call,
+        // the death signal will never be sent, so exit now.
+        // getppid(2) cannot detect this, as the parent is
+        // outside the PID namespace and so it always returns zero.
+        let mut parent_pollfd = libc::pollfd{
+            fd: parent_pidfd.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let parent_poll = unsafe { libc::poll(&mut parent_pollfd, 1, 0) };
+        enforce("poll parent pidfd", parent_poll != -1);
+        if parent_poll != 0 {
+            unsafe { libc::_exit(1); }
+        }
+
+        // Put the child in its own process group,
+        // so that it can be killed together with its descendants.
+        // The parent is blocked on the pipe, so it cannot kill us yet.
+        let setpgid = setpgid(0, 0);
+        enforce("setpgid", setpgid.is_ok());
+
+        // Prevent the command from gaining privileges through execve,
+        // e.g. by running set-user-ID programs.
+        let no_new_privs = prctl_set_no_new_privs();
+        enforce("prctl PR_SET_NO_NEW_PRIVS", no_new_privs.is_ok());
+
         // Write the /proc/self/\* files prepared above.
         unsafe {
             let write_file = |pathname: &'static [u8], data: &[u8]| {
@@ -549,12 +1153,25 @@ fn run_command(
             write_file(b"/proc/self/gid_map\0", gid_map.as_bytes());
         }
 
+        // Set the hostname of the new UTS namespace.
+        // Otherwise it would be inherited from the host.
+        let sethostname = unsafe {
+            libc::syscall(libc::SYS_sethostname, hostname.as_ptr(), hostname.len())
+        };
+        enforce("sethostname", sethostname != -1);
+
         // Configure the standard streams stdin, stdout, and stderr.
         // dup2 turns off CLOEXEC which is exactly what we need.
         let build_log = build_log.as_raw_fd();
+        let stdout = stdout.map_or(build_log, |fd| fd.as_raw_fd());
         unsafe {
-            enforce("close stdin", libc::close(0) != -1);
-            enforce("dup2 stdout", libc::dup2(build_log, 1) != -1);
+            match &stdin_pipe {
+                Some((stdin_r, _)) =>
+                    enforce("dup2 stdin", libc::dup2(stdin_r.as_raw_fd(), 0) != -1),
+                None =>
+                    enforce("close stdin", libc::close(0) != -1),
+            }
+            enforce("dup2 stdout", libc::dup2(stdout, 1) != -1);
             enforce("dup2 stderr", libc::dup2(build_log, 2) != -1);
         }
 
@@ -579,6 +1196,23 @@ fn run_command(
         let chdir = unsafe { libc::chdir(b"/build\0".as_ptr().cast()) };
         enforce("chdir", chdir != -1);
 
+        // Change the working directory to the requested subdirectory,
+        // creating any of its components that do not exist yet.
+        for component in working_directory {
+            let mkdir = unsafe { libc::mkdir(component.as_ptr(), 0o755) };
+            let errno = unsafe { *libc::__errno_location() };
+            enforce("mkdir", mkdir != -1 || errno == libc::EEXIST);
+            let chdir = unsafe { libc::chdir(component.as_ptr()) };
+            enforce("chdir", chdir != -1);
+        }
+
+        // Install the seccomp filter as late as possible,
+        // so that it does not interfere with setting up the container.
+        if let Some(seccomp_filter) = &seccomp_filter {
+            let seccomp = prctl_set_seccomp_filter(seccomp_filter);
+            enforce("prctl PR_SET_SECCOMP", seccomp.is_ok());
+        }
+
         // Run the specified program.
         unsafe { libc::execve(program.as_ptr(), execve_argv, execve_envp) };
         enforce("execve", false);
@@ -602,16 +1236,16 @@ fn run_command(
     // If any of the code below fails, kill the child.
     // SIGKILL is normally frowned upon; the child gets no chance to clean up.
     // But in our case the child is sandboxed; there is nothing to clean up.
-    let child_guard = ScopeExit::new(|| {
-        unsafe { libc::kill(pid, libc::SIGKILL); }
-        unsafe { libc::waitpid(pid, null_mut(), libc::WNOHANG); }
-    });
+    // Waiting for the child also waits for the rest of its PID namespace,
+    // so that afterwards the transient cgroup is empty and can be removed.
+    let child_guard = ScopeExit::new(|| { kill_and_reap(pid); });
 
     // SAFETY: clone3 created a valid file descriptor.
     let pidfd = unsafe { OwnedFd::from_raw_fd(pidfd) };
 
-    // Close the write end of the pipe.
+    // Close the write end of the pipe and the pidfd only the child uses.
     drop(pipe_w);
+    drop(parent_pidfd);
 
     // Read from the read end of the pipe.
     // On EOF, we know that execve was successful.
@@ -630,6 +1264,18 @@ fn run_command(
             .map_err(Error::from);
     }
 
+    // Feed the standard input from a separate thread,
+    // so that the timeout is enforced even if the child does not read it.
+    if let (Some(stdin), Some((stdin_r, stdin_w))) = (stdin, stdin_pipe) {
+        drop(stdin_r);
+        let stdin = stdin.to_vec();
+        thread::spawn(move || {
+            // The child need not read all of its standard input,
+            // in which case writing fails with EPIPE, which is fine.
+            let _ = File::from(stdin_w).write_all(&stdin);
+        });
+    }
+
     // A pidfd reports "readable" when the child terminates.
     // We don't need to actually read from the pidfd, only ppoll.
     let mut pollfd = libc::pollfd{
@@ -638,22 +1284,47 @@ fn run_command(
         revents: 0,
     };
 
-    // Convert timeout from Duration to libc::timespec.
-    let ptimeout = libc::timespec{
-        tv_sec: timeout.as_secs().try_into().unwrap_or(libc::time_t::MAX),
-        tv_nsec: timeout.subsec_nanos().try_into().unwrap_or(libc::c_long::MAX),
-    };
-
     // Wait for the child to terminate or the timeout to occur.
-    let ppoll = unsafe { libc::ppoll(&mut pollfd, 1, &ptimeout, null()) };
-    if ppoll == -1 {
-        let error = io::Error::last_os_error();
-        return Err(anyhow::Error::from(error))
-            .with_context(|| "Poll child process")
-            .map_err(Error::from);
-    }
-    if ppoll == 0 {
-        return Err(Error::Timeout(timeout));
+    // If the build log is limited, wake up periodically to check its size.
+    let deadline = Instant::now() + *timeout;
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let interval = match log_limit {
+            Some(_) => remaining.min(LOG_LIMIT_INTERVAL),
+            None => remaining,
+        };
+
+        // Convert interval from Duration to libc::timespec.
+        let ptimeout = libc::timespec{
+            tv_sec: interval.as_secs().try_into().unwrap_or(libc::time_t::MAX),
+            tv_nsec: interval.subsec_nanos().try_into().unwrap_or(libc::c_long::MAX),
+        };
+
+        let ppoll = unsafe { libc::ppoll(&mut pollfd, 1, &ptimeout, null()) };
+        if ppoll == -1 {
+            let error = io::Error::last_os_error();
+            return Err(anyhow::Error::from(error))
+                .with_context(|| "Poll child process")
+                .map_err(Error::from);
+        }
+        if ppoll != 0 {
+            break;
+        }
+
+        if let Some(log_limit) = log_limit {
+            let statbuf = fstatat(Some(build_log), cstr!(b""), AT_EMPTY_PATH)   .with_context(|| "Find size of build log")?;
+            if statbuf.st_size as u64 > *log_limit {
+                return Err(Error::LogTooLarge(*log_limit));
+            }
+        }
+
+        if interval == remaining {
+            forget(child_guard);
+            let killed = kill_and_reap(pid);
+            let statbuf = fstatat(Some(build_log), cstr!(b""), AT_EMPTY_PATH)   .with_context(|| "Find size of build log")?;
+            let log_bytes = statbuf.st_size as u64;
+            return Err(Error::Timeout{after: *timeout, killed, log_bytes});
+        }
     }
 
     // The child has terminated, so no need to kill it.
@@ -664,11 +1335,102 @@ fn run_command(
     let mut wstatus = 0;
     let waitpid = unsafe { libc::waitpid(pid, &mut wstatus, 0) };
     assert_eq!(waitpid, pid, "pidfd reported that child has terminated");
-    ExitStatus::from_raw(wstatus).exit_ok()?;
+    let status = ExitStatus::from_raw(wstatus);
+    if !status.success() {
+        let log_tail = read_log_tail(build_log, *log_tail_lines)?;
+        return Err(Error::Command{status, log_tail});
+    }
 
     Ok(())
 }
 
+/// Kill the child and its descendants and wait for them to terminate.
+///
+/// The signal is sent to the process group of the child,
+/// which the child creates before executing the command,
+/// and to the child itself, in case it has not done so yet.
+/// Descendants that moved to another process group are not signalled,
+/// but the child is the init process of its PID namespace.
+/// When the init process dies, the kernel kills the rest of the namespace,
+/// and waitpid does not report the init process as terminated
+/// until every other process in the namespace has been reaped.
+/// So once this function returns, no descendants linger.
+///
+/// Returns whether the child was terminated by the kill,
+/// as opposed to having exited by itself.
+fn kill_and_reap(pid: libc::pid_t) -> bool
+{
+    let mut wstatus = 0;
+    unsafe { libc::kill(-pid, libc::SIGKILL); }
+
+    // If the child has not called setpgid yet, the above fails with ESRCH.
+    // Killing the child directly always works, and since it is
+    // the init process of its PID namespace, that tears down the namespace.
+    unsafe { libc::kill(pid, libc::SIGKILL); }
+
+    let waitpid = unsafe { libc::waitpid(pid, &mut wstatus, 0) };
+    waitpid == pid
+        && libc::WIFSIGNALED(wstatus)
+        && libc::WTERMSIG(wstatus) == libc::SIGKILL
+}
+
+/// Maximum number of bytes [`read_log_tail`] reads per requested line.
+const MAX_LOG_TAIL_LINE_LENGTH: u64 = 8192;
+
+/// Read the last `lines` lines of the build log.
+///
+/// The build log is read backwards in chunks,
+/// so that long build logs need not be read in full.
+/// At most [`MAX_LOG_TAIL_LINE_LENGTH`] bytes are read per requested line,
+/// so a build log with very long lines is truncated at the front.
+fn read_log_tail(build_log: BorrowedFd, lines: usize) -> Result<Vec<u8>, Error>
+{
+    const CHUNK_SIZE: u64 = 4096;
+
+    if lines == 0 {
+        return Ok(Vec::new());
+    }
+
+    // A trailing newline terminates the last line; it does not begin one.
+    let without_last_newline =
+        |tail: &[u8]| tail.strip_suffix(b"\n").unwrap_or(tail).len();
+    let count_newlines = |tail: &[u8]|
+        tail[.. without_last_newline(tail)]
+        .iter().filter(|&&b| b == b'\n').count();
+
+    let build_log = build_log.try_to_owned()                                    .with_context(|| "Duplicate build log file descriptor")?;
+    let build_log = File::from(build_log);
+    let mut offset = build_log.metadata()                                       .with_context(|| "Find size of build log")?.len();
+
+    // Read chunks until enough lines, the byte cap,
+    // or the start of the file are found.
+    let max_bytes = (lines as u64).saturating_mul(MAX_LOG_TAIL_LINE_LENGTH);
+    let mut tail = Vec::new();
+    while offset != 0
+        && (tail.len() as u64) < max_bytes
+        && count_newlines(&tail) < lines
+    {
+        let remaining = max_bytes - tail.len() as u64;
+        let chunk_size = offset.min(CHUNK_SIZE).min(remaining);
+        offset -= chunk_size;
+        let mut chunk = vec![0; chunk_size as usize];
+        build_log.read_exact_at(&mut chunk, offset)                             .with_context(|| "Read from build log")?;
+        chunk.extend_from_slice(&tail);
+        tail = chunk;
+    }
+
+    // Discard any lines in excess of the requested number.
+    let start =
+        tail[.. without_last_newline(&tail)]
+        .iter().enumerate().rev()
+        .filter(|(_, &b)| b == b'\n')
+        .nth(lines - 1)
+        .map_or(0, |(i, _)| i + 1);
+    tail.drain(.. start);
+
+    Ok(tail)
+}
+
 /// Arguments to the clone3 system call.
 ///
 /// This struct is unfortunately not part of the libc crate.
@@ -688,6 +1450,11 @@ struct clone_args
     cgroup:       u64,
 }
 
+/// Flag for the clone3 system call.
+///
+/// This constant is unfortunately not part of the libc crate.
+const CLONE_INTO_CGROUP: u64 = 0x200000000;
+
 /// Prepare the argv or envp arguments to `execve`.
 ///
 /// `execve` expects these to be arrays of nul-terminated strings,
@@ -715,14 +1482,16 @@ mod tests
     use {
         super::*,
         os_ext::{
-            O_DIRECTORY, O_PATH, O_RDWR, O_TMPFILE,
-            cstr, cstring, mkdtemp, open,
+            O_DIRECTORY, O_PATH, O_RDONLY, O_RDWR, O_TMPFILE, S_IFIFO,
+            cstr, cstring, open,
+            temp_dir::TempDir,
         },
         std::{
             assert_matches::assert_matches,
-            io::Seek,
+            ffi::OsStr,
+            io::{Seek, Write},
             ops::Deref,
-            os::unix::io::AsFd,
+            os::unix::{ffi::OsStringExt, io::AsFd, net::UnixListener},
         },
     };
 
@@ -733,9 +1502,8 @@ mod tests
         input_paths: &[InputPath],
     ) -> (Result<Success, Error>, File)
     {
-        let path      = mkdtemp(cstring!(b"/tmp/snowflake-test-XXXXXX")).unwrap();
+        let scratch   = TempDir::new(cstring!(b"/tmp/snowflake-test-XXXXXX")).unwrap();
         let build_log = open(cstr!(b"."), O_RDWR | O_TMPFILE, 0o644).unwrap();
-        let scratch   = open(&path, O_DIRECTORY | O_PATH, 0).unwrap();
 
         let perform = Perform{
             build_log: build_log.as_fd(),
@@ -751,52 +1519,182 @@ mod tests
     }
 
     #[test]
-    fn inputs()
+    fn cgroup_limits()
     {
-        let coreutils = env!("SNOWFLAKE_COREUTILS");
+        // This test requires a delegated cgroup with the memory controller,
+        // which is usually not available, so it must be enabled explicitly.
+        let Some(parent) = std::env::var_os("SNOWFLAKE_TEST_CGROUP")
+            else { return };
 
-        let inputs: Vec<Basename<CString>> =
-            [
-                cstring!(b"regular.txt"),
-                cstring!(b"directory"),
-                cstring!(b"symlink.lnk"),
-                cstring!(b"broken.lnk"),
-            ]
-            .into_iter()
-            .map(|i| Basename::new(i).unwrap())
-            .collect();
+        let cgroups_before = fs::read_dir(&parent).unwrap().count();
 
-        let source_root =
-            open(cstr!(b"testdata/inputs"), O_DIRECTORY | O_PATH, 0)
-                .unwrap();
+        let action = RunCommand{
+            timeout: Duration::from_secs(5),
+            cgroup_limits: Some(CgroupLimits{
+                parent: parent.clone().into(),
+                memory_max: Some(16 << 20),
+                cpu_max: None,
+            }),
+            ..RunCommand::new(
+                cstring!(b"/bin/sh"),
+                vec![
+                    cstring!(b"sh"),
+                    cstring!(b"-c"),
+                    cstring!(b"x=x; while true; do x=$x$x; done"),
+                ],
+            )
+        };
+        let (result, _) = call_perform_run_command(&action, &[]);
+        assert_matches!(
+            result,
+            Err(Error::Command{status, ..})
+                if status.signal() == Some(libc::SIGKILL)
+        );
+
+        // The transient cgroup must have been removed.
+        let cgroups_after = fs::read_dir(&parent).unwrap().count();
+        assert_eq!(cgroups_after, cgroups_before);
+    }
 
-        let input_paths: Vec<InputPath> =
-            inputs.iter()
-            .map(|i| InputPath{
-                dirfd: source_root.as_fd(),
-                path: Cow::Owned(i.deref().to_owned()),
-            })
-            .collect();
+    #[test]
+    fn hash_environment_order()
+    {
+        let hash = |environment| RunCommand{
+            environment,
+            timeout: Duration::from_secs(1),
+            ..RunCommand::new(cstring!(b"/bin/sh"), vec![])
+        }.hash(&[]);
+
+        let a = hash(vec![cstring!(b"A=1"), cstring!(b"B=2")]);
+        let b = hash(vec![cstring!(b"B=2"), cstring!(b"A=1")]);
+        let c = hash(vec![cstring!(b"A=2"), cstring!(b"B=1")]);
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+
+        // The first of duplicate variables takes effect.
+        let d = hash(vec![cstring!(b"A=1"), cstring!(b"A=2")]);
+        let e = hash(vec![cstring!(b"A=2"), cstring!(b"A=1")]);
+        assert_ne!(d, e);
+    }
+
+    #[test]
+    fn hash_default_hostname()
+    {
+        let hash = |hostname| RunCommand{
+            hostname,
+            ..RunCommand::new(cstring!(b"/bin/sh"), vec![])
+        }.hash(&[]);
+
+        let a = hash(None);
+        let b = hash(Some(cstring!(b"snowflake")));
+        let c = hash(Some(cstring!(b"example")));
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+    }
+
+    #[test]
+    fn hash_id_map()
+    {
+        let hash = |uid_map| RunCommand{
+            uid_map,
+            ..RunCommand::new(cstring!(b"/bin/sh"), vec![])
+        }.hash(&[]);
+
+        let a = hash(vec![]);
+        let b = hash(vec![IdMapping{inside: 0, outside: 1000, count: 1}]);
+        let c = hash(vec![IdMapping{inside: 0, outside: 2000, count: 1}]);
+        let d = hash(vec![IdMapping{inside: 1000, outside: 1000, count: 1}]);
+        assert_eq!(a, b);
+        assert_eq!(b, c);
+        assert_ne!(b, d);
+    }
+
+    #[test]
+    fn hash_empty_seccomp()
+    {
+        let hash = |seccomp| RunCommand{
+            seccomp,
+            ..RunCommand::new(cstring!(b"/bin/sh"), vec![])
+        }.hash(&[]);
+
+        let a = hash(None);
+        let b = hash(Some(SeccompPolicy::new()));
+        let c = hash(Some(SeccompPolicy::new().deny(libc::SYS_chdir)));
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+    }
+
+    #[test]
+    fn hostname()
+    {
+        let coreutils = CString::new(env!("SNOWFLAKE_COREUTILS")).unwrap();
+        for (hostname, expected) in [
+            (None,                      "snowflake\n"),
+            (Some(cstring!(b"example")), "example\n"),
+        ] {
+            let action = RunCommand{
+                timeout: Duration::from_millis(50),
+                hostname,
+                ..RunCommand::new(
+                    coreutils.join(cstr!(b"bin/uname")),
+                    vec![cstring!(b"uname"), cstring!(b"-n")],
+                )
+            };
+            let (result, mut build_log) = call_perform_run_command(&action, &[]);
+            assert_matches!(result, Ok(Success{warnings: false, ..}));
+            let mut buf = String::new();
+            build_log.read_to_string(&mut buf).unwrap();
+            assert_eq!(buf, expected);
+        }
+    }
+
+    #[test]
+    fn inputs()
+    {
+        let coreutils = env!("SNOWFLAKE_COREUTILS");
+
+        let inputs: Vec<Basename<CString>> =
+            [
+                cstring!(b"regular.txt"),
+                cstring!(b"directory"),
+                cstring!(b"symlink.lnk"),
+                cstring!(b"broken.lnk"),
+            ]
+            .into_iter()
+            .map(|i| Basename::new(i).unwrap())
+            .collect();
+
+        let source_root =
+            open(cstr!(b"testdata/inputs"), O_DIRECTORY | O_PATH, 0)
+                .unwrap();
+
+        let input_paths: Vec<InputPath> =
+            inputs.iter()
+            .map(|i| InputPath{
+                dirfd: source_root.as_fd(),
+                path: Cow::Owned(i.deref().to_owned()),
+            })
+            .collect();
 
         let action = RunCommand{
             inputs,
-            outputs: Outputs::Outputs(vec![]),
-            program: cstring!(b"/bin/sh"),
-            arguments: vec![
-                cstring!(b"sh"),
-                cstring!(b"-c"),
-                cstring!(br#"
-                    cat regular.txt
-                    ls directory
-                    readlink symlink.lnk
-                    readlink broken.lnk
-                "#),
-            ],
             environment: vec![
                 CString::new(format!("PATH={coreutils}/bin")).unwrap(),
             ],
             timeout: Duration::from_millis(50),
-            warnings: None,
+            ..RunCommand::new(
+                cstring!(b"/bin/sh"),
+                vec![
+                    cstring!(b"sh"),
+                    cstring!(b"-c"),
+                    cstring!(br#"
+                        cat regular.txt
+                        ls directory
+                        readlink symlink.lnk
+                        readlink broken.lnk
+                    "#),
+                ],
+            )
         };
 
         let (result, mut build_log) =
@@ -813,17 +1711,15 @@ mod tests
     fn pid_1()
     {
         let action = RunCommand{
-            inputs: vec![],
-            outputs: Outputs::Outputs(vec![]),
-            program: cstring!(b"/bin/sh"),
-            arguments: vec![
-                cstring!(b"sh"),
-                cstring!(b"-c"),
-                cstring!(b"echo $$"),
-            ],
-            environment: vec![],
             timeout: Duration::from_millis(50),
-            warnings: None,
+            ..RunCommand::new(
+                cstring!(b"/bin/sh"),
+                vec![
+                    cstring!(b"sh"),
+                    cstring!(b"-c"),
+                    cstring!(b"echo $$"),
+                ],
+            )
         };
         let (result, mut build_log) = call_perform_run_command(&action, &[]);
         assert_matches!(result, Ok(Success{warnings: false, ..}));
@@ -832,21 +1728,520 @@ mod tests
         assert_eq!(buf, b"1\n");
     }
 
+    #[test]
+    fn inputs_duplicate_basename()
+    {
+        let inputs = vec![
+            Basename::new(cstring!(b"regular.txt")).unwrap(),
+            Basename::new(cstring!(b"regular.txt")).unwrap(),
+        ];
+
+        let source_root =
+            open(cstr!(b"testdata/inputs"), O_DIRECTORY | O_PATH, 0)
+                .unwrap();
+
+        let input_paths = vec![
+            InputPath{
+                dirfd: source_root.as_fd(),
+                path: Cow::Borrowed(cstr!(b"regular.txt")),
+            },
+            InputPath{
+                dirfd: source_root.as_fd(),
+                path: Cow::Borrowed(cstr!(b"directory")),
+            },
+        ];
+
+        let action = RunCommand{
+            inputs,
+            timeout: Duration::from_millis(50),
+            ..RunCommand::new(
+                cstring!(b"/bin/sh"),
+                vec![cstring!(b"sh"), cstring!(b"-c"), cstring!(b"")],
+            )
+        };
+
+        let (result, _) = call_perform_run_command(&action, &input_paths);
+        assert_matches!(
+            result,
+            Err(Error::Unexpected(err))
+                if err.to_string().starts_with("Duplicate input basename")
+        );
+    }
+
+    #[test]
+    fn inputs_symlink_to_input()
+    {
+        let coreutils = env!("SNOWFLAKE_COREUTILS");
+
+        // The symbolic link points to the other input.
+        let inputs: Vec<Basename<CString>> =
+            [cstring!(b"symlink.lnk"), cstring!(b"regular.txt")]
+            .into_iter()
+            .map(|i| Basename::new(i).unwrap())
+            .collect();
+
+        let source_root =
+            open(cstr!(b"testdata/inputs"), O_DIRECTORY | O_PATH, 0)
+                .unwrap();
+
+        let input_paths: Vec<InputPath> =
+            inputs.iter()
+            .map(|i| InputPath{
+                dirfd: source_root.as_fd(),
+                path: Cow::Owned(i.deref().to_owned()),
+            })
+            .collect();
+
+        let action = RunCommand{
+            inputs,
+            environment: vec![
+                CString::new(format!("PATH={coreutils}/bin")).unwrap(),
+            ],
+            timeout: Duration::from_millis(50),
+            ..RunCommand::new(
+                cstring!(b"/bin/sh"),
+                vec![
+                    cstring!(b"sh"),
+                    cstring!(b"-c"),
+                    cstring!(b"readlink symlink.lnk; cat symlink.lnk"),
+                ],
+            )
+        };
+
+        let (result, mut build_log) =
+            call_perform_run_command(&action, &input_paths);
+
+        assert_matches!(result, Ok(Success{warnings: false, ..}));
+        let mut buf = String::new();
+        build_log.read_to_string(&mut buf).unwrap();
+        assert_eq!(buf, "regular.txt\nHello, world!\n");
+    }
+
+    #[test]
+    fn inputs_unsupported_file_type()
+    {
+        let source_root = TempDir::new(cstring!(b"/tmp/snowflake-test-XXXXXX")).unwrap();
+        mknodat(Some(source_root.as_fd()), cstr!(b"fifo"), S_IFIFO | 0o644, 0).unwrap();
+        let socket_path = source_root.path().join(cstr!(b"socket"));
+        let socket_path = OsStr::from_bytes(socket_path.to_bytes());
+        let _socket = UnixListener::bind(socket_path).unwrap();
+
+        for basename in [cstr!(b"fifo"), cstr!(b"socket")] {
+            let action = RunCommand{
+                inputs: vec![Basename::new(basename.to_owned()).unwrap()],
+                timeout: Duration::from_millis(50),
+                ..RunCommand::new(
+                    cstring!(b"/bin/sh"),
+                    vec![cstring!(b"sh"), cstring!(b"-c"), cstring!(b"")],
+                )
+            };
+
+            let input_paths = [InputPath{
+                dirfd: source_root.as_fd(),
+                path: Cow::Borrowed(basename),
+            }];
+
+            let (result, _) = call_perform_run_command(&action, &input_paths);
+            assert_matches!(result, Err(Error::Unexpected(_)));
+        }
+    }
+
+    #[test]
+    fn log_limit()
+    {
+        let coreutils = CString::new(env!("SNOWFLAKE_COREUTILS")).unwrap();
+        let action = RunCommand{
+            timeout: Duration::from_secs(5),
+            log_limit: Some(1 << 20),
+            ..RunCommand::new(
+                coreutils.join(cstr!(b"bin/yes")),
+                vec![cstring!(b"yes")],
+            )
+        };
+        let (result, _) = call_perform_run_command(&action, &[]);
+        assert_matches!(result, Err(Error::LogTooLarge(limit)) if limit == 1 << 20);
+    }
+
+    #[test]
+    fn missing_output()
+    {
+        let action = RunCommand{
+            outputs: Outputs::Outputs(vec![
+                Basename::new(cstring!(b"present")).unwrap(),
+                Basename::new(cstring!(b"missing")).unwrap(),
+            ]),
+            timeout: Duration::from_millis(50),
+            ..RunCommand::new(
+                cstring!(b"/bin/sh"),
+                vec![
+                    cstring!(b"sh"),
+                    cstring!(b"-c"),
+                    cstring!(b"echo > present"),
+                ],
+            )
+        };
+        let (result, _) = call_perform_run_command(&action, &[]);
+        assert_matches!(
+            result,
+            Err(Error::MissingOutput{basename})
+                if basename.as_bytes() == b"missing"
+        );
+    }
+
+    #[test]
+    fn unsupported_output_type()
+    {
+        let coreutils = CString::new(env!("SNOWFLAKE_COREUTILS")).unwrap();
+        let action = RunCommand{
+            outputs: Outputs::Outputs(vec![
+                Basename::new(cstring!(b"fifo")).unwrap(),
+            ]),
+            timeout: Duration::from_millis(50),
+            ..RunCommand::new(
+                coreutils.join(cstr!(b"bin/mkfifo")),
+                vec![cstring!(b"mkfifo"), cstring!(b"fifo")],
+            )
+        };
+        let (result, _) = call_perform_run_command(&action, &[]);
+        assert_matches!(
+            result,
+            Err(Error::UnsupportedOutputType{basename})
+                if basename.as_bytes() == b"fifo"
+        );
+    }
+
+    /// Environment variable that instructs [`parent_death_helper`]
+    /// in which directory to perform the action.
+    const PARENT_DEATH_DIR: &str = "SNOWFLAKE_TEST_PARENT_DEATH_DIR";
+
+    #[test]
+    fn parent_death()
+    {
+        let dir = TempDir::new(cstring!(b"/tmp/snowflake-test-XXXXXX")).unwrap();
+        let dir_path = OsStr::from_bytes(dir.path().to_bytes());
+
+        // Perform the action in a freshly executed helper process,
+        // so that we can kill the parent of the command.
+        // Forking the multi-threaded test process is not safe.
+        let mut helper =
+            process::Command::new(std::env::current_exe().unwrap())
+            .args(["--exact", "run_command::tests::parent_death_helper"])
+            .args(["--ignored", "--quiet"])
+            .env(PARENT_DEATH_DIR, dir_path)
+            .stdout(process::Stdio::null())
+            .spawn()
+            .unwrap();
+
+        // Kill the parent of the command while the command is running.
+        std::thread::sleep(Duration::from_millis(200));
+        helper.kill().unwrap();
+        helper.wait().unwrap();
+
+        // Give the command the chance to finish, which it must not.
+        std::thread::sleep(Duration::from_millis(700));
+        let build_log = openat(Some(dir.as_fd()), cstr!(b"build.log"), O_RDONLY, 0).unwrap();
+        let mut buf = String::new();
+        File::from(build_log).read_to_string(&mut buf).unwrap();
+        assert_eq!(buf, "before\n");
+    }
+
+    /// Helper process for [`parent_death`].
+    #[test]
+    #[ignore]
+    fn parent_death_helper()
+    {
+        let Some(dir_path) = std::env::var_os(PARENT_DEATH_DIR) else { return };
+        let dir_path = CString::new(dir_path.into_vec()).unwrap();
+        let dir = open(&dir_path, O_DIRECTORY | O_PATH, 0).unwrap();
+
+        let action = RunCommand{
+            timeout: Duration::from_secs(5),
+            ..RunCommand::new(
+                cstring!(b"/bin/sh"),
+                vec![
+                    cstring!(b"sh"),
+                    cstring!(b"-c"),
+                    cstring!(b"echo before; sleep 0.5; echo after"),
+                ],
+            )
+        };
+
+        mkdirat(Some(dir.as_fd()), cstr!(b"scratch"), 0o755).unwrap();
+        let scratch   = openat(Some(dir.as_fd()), cstr!(b"scratch"), O_DIRECTORY | O_PATH, 0).unwrap();
+        let build_log = openat(Some(dir.as_fd()), cstr!(b"build.log"), O_CREAT | O_RDWR, 0o644).unwrap();
+
+        let perform = Perform{
+            build_log: build_log.as_fd(),
+            scratch: scratch.as_fd(),
+        };
+        let _ = perform_run_command(&perform, &action, &[]);
+    }
+
+    #[test]
+    fn provide_passwd()
+    {
+        let coreutils = CString::new(env!("SNOWFLAKE_COREUTILS")).unwrap();
+        let action = RunCommand{
+            timeout: Duration::from_millis(100),
+            uid_map: vec![IdMapping{inside: 1000, outside: getuid(), count: 1}],
+            provide_passwd: true,
+            ..RunCommand::new(
+                coreutils.join(cstr!(b"bin/id")),
+                vec![cstring!(b"id"), cstring!(b"-un")],
+            )
+        };
+        let (result, mut build_log) = call_perform_run_command(&action, &[]);
+        assert_matches!(result, Ok(Success{warnings: false, ..}));
+        let mut buf = String::new();
+        build_log.read_to_string(&mut buf).unwrap();
+        assert_eq!(buf, "build\n");
+    }
+
+    #[test]
+    fn seccomp()
+    {
+        let action = RunCommand{
+            timeout: Duration::from_millis(50),
+            seccomp: Some(SeccompPolicy::recommended().deny(libc::SYS_chdir)),
+            ..RunCommand::new(
+                cstring!(b"/bin/sh"),
+                vec![
+                    cstring!(b"sh"),
+                    cstring!(b"-c"),
+                    cstring!(b"cd /"),
+                ],
+            )
+        };
+        let (result, _) = call_perform_run_command(&action, &[]);
+        assert_matches!(
+            result,
+            Err(Error::Command{log_tail, ..})
+                if log_tail.ends_with(b"Operation not permitted\n")
+        );
+    }
+
+    #[test]
+    fn stdin()
+    {
+        let coreutils = CString::new(env!("SNOWFLAKE_COREUTILS")).unwrap();
+
+        // Larger than the pipe buffer, to check that writing doesn't block.
+        let stdin = "Hello, stdin!\n".repeat(100_000);
+
+        let action = RunCommand{
+            stdin: Some(stdin.clone().into_bytes()),
+            timeout: Duration::from_secs(1),
+            ..RunCommand::new(
+                coreutils.join(cstr!(b"bin/cat")),
+                vec![cstring!(b"cat")],
+            )
+        };
+        let (result, mut build_log) = call_perform_run_command(&action, &[]);
+        assert_matches!(result, Ok(Success{warnings: false, ..}));
+        let mut buf = String::new();
+        build_log.read_to_string(&mut buf).unwrap();
+        assert!(buf == stdin);
+    }
+
+    #[test]
+    fn argv0()
+    {
+        // Bash enters POSIX mode when its zeroth argument is "sh".
+        let run = |argv0: &[u8]| {
+            let action = RunCommand{
+                argv0: Some(CString::new(argv0).unwrap()),
+                timeout: Duration::from_millis(50),
+                ..RunCommand::new(
+                    cstring!(b"/bin/sh"),
+                    vec![
+                        cstring!(b"ignored"),
+                        cstring!(b"-c"),
+                        cstring!(b"echo $0; shopt -qo posix && echo posix || echo bash"),
+                    ],
+                )
+            };
+            let (result, mut build_log) = call_perform_run_command(&action, &[]);
+            assert_matches!(result, Ok(Success{warnings: false, ..}));
+            let mut buf = String::new();
+            build_log.read_to_string(&mut buf).unwrap();
+            (buf, action.hash(&[]))
+        };
+
+        let (log_sh, hash_sh) = run(b"sh");
+        let (log_bash, hash_bash) = run(b"bash");
+        assert_eq!(log_sh, "sh\nposix\n");
+        assert_eq!(log_bash, "bash\nbash\n");
+        assert_ne!(hash_sh, hash_bash);
+    }
+
+    #[test]
+    fn stdout_output()
+    {
+        let action = RunCommand{
+            outputs: Outputs::Outputs(vec![
+                Basename::new(cstring!(b"out")).unwrap(),
+            ]),
+            stdout_output: Some(0),
+            timeout: Duration::from_millis(50),
+            ..RunCommand::new(
+                cstring!(b"/bin/sh"),
+                vec![
+                    cstring!(b"sh"),
+                    cstring!(b"-c"),
+                    cstring!(b"echo hi; echo oops >&2"),
+                ],
+            )
+        };
+
+        let scratch   = TempDir::new(cstring!(b"/tmp/snowflake-test-XXXXXX")).unwrap();
+        let build_log = open(cstr!(b"."), O_RDWR | O_TMPFILE, 0o644).unwrap();
+        let perform = Perform{
+            build_log: build_log.as_fd(),
+            scratch: scratch.as_fd(),
+        };
+        let result = perform_run_command(&perform, &action, &[]);
+        assert_matches!(result, Ok(Success{warnings: false, ..}));
+
+        let output = openat(Some(scratch.as_fd()), cstr!(b"build/out"), O_RDONLY, 0).unwrap();
+        let mut buf = String::new();
+        File::from(output).read_to_string(&mut buf).unwrap();
+        assert_eq!(buf, "hi\n");
+
+        let mut build_log = File::from(build_log);
+        build_log.rewind().unwrap();
+        let mut buf = String::new();
+        build_log.read_to_string(&mut buf).unwrap();
+        assert_eq!(buf, "oops\n");
+    }
+
     #[test]
     fn timeout()
     {
         let coreutils = CString::new(env!("SNOWFLAKE_COREUTILS")).unwrap();
         let action = RunCommand{
-            inputs: vec![],
-            outputs: Outputs::Outputs(vec![]),
-            program: coreutils.join(cstr!(b"bin/sleep")),
-            arguments: vec![cstring!(b"sleep"), cstring!(b"0.060")],
-            environment: vec![],
             timeout: Duration::from_millis(50),
-            warnings: None,
+            ..RunCommand::new(
+                cstring!(b"/bin/sh"),
+                vec![
+                    cstring!(b"sh"),
+                    cstring!(b"-c"),
+                    CString::new([
+                        b"echo partial; exec ",
+                        coreutils.join(cstr!(b"bin/sleep")).to_bytes(),
+                        b" 1",
+                    ].concat()).unwrap(),
+                ],
+            )
         };
         let (result, _) = call_perform_run_command(&action, &[]);
-        assert_matches!(result, Err(Error::Timeout(_)));
+        assert_matches!(
+            result,
+            Err(Error::Timeout{after, killed: true, log_bytes: 8})
+                if after == Duration::from_millis(50)
+        );
+    }
+
+    #[test]
+    fn timeout_kills_descendants()
+    {
+        let coreutils = CString::new(env!("SNOWFLAKE_COREUTILS")).unwrap();
+        let sleep = coreutils.join(cstr!(b"bin/sleep"));
+        let sleep = sleep.to_bytes();
+        let action = RunCommand{
+            timeout: Duration::from_millis(100),
+            ..RunCommand::new(
+                cstring!(b"/bin/sh"),
+                vec![
+                    cstring!(b"sh"),
+                    cstring!(b"-c"),
+                    CString::new([
+                        b"(", sleep, b" 0.3; echo leaked) & ",
+                        b"echo started; exec ", sleep, b" 1",
+                    ].concat()).unwrap(),
+                ],
+            )
+        };
+        let (result, mut build_log) = call_perform_run_command(&action, &[]);
+        assert_matches!(result, Err(Error::Timeout{killed: true, ..}));
+
+        // Give the grandchild the chance to finish, which it must not.
+        std::thread::sleep(Duration::from_millis(500));
+        let mut buf = String::new();
+        build_log.read_to_string(&mut buf).unwrap();
+        assert_eq!(buf, "started\n");
+    }
+
+    #[test]
+    fn tmp()
+    {
+        for tmp_size in [None, Some(1 << 20)] {
+            let action = RunCommand{
+                timeout: Duration::from_millis(50),
+                tmp_size,
+                ..RunCommand::new(
+                    cstring!(b"/bin/sh"),
+                    vec![
+                        cstring!(b"sh"),
+                        cstring!(b"-c"),
+                        cstring!(br#"
+                            [ -k /tmp ] && echo sticky
+                            echo hello > /tmp/hello
+                            read x < /tmp/hello
+                            echo $x
+                        "#),
+                    ],
+                )
+            };
+            let (result, mut build_log) = call_perform_run_command(&action, &[]);
+            assert_matches!(result, Ok(Success{warnings: false, ..}));
+            let mut buf = String::new();
+            build_log.read_to_string(&mut buf).unwrap();
+            assert_eq!(buf, "sticky\nhello\n");
+        }
+    }
+
+    #[test]
+    fn uid_map()
+    {
+        let coreutils = CString::new(env!("SNOWFLAKE_COREUTILS")).unwrap();
+        let id = coreutils.join(cstr!(b"bin/id"));
+        let action = RunCommand{
+            timeout: Duration::from_millis(100),
+            uid_map: vec![IdMapping{inside: 1000, outside: getuid(), count: 1}],
+            gid_map: vec![IdMapping{inside: 100, outside: getgid(), count: 1}],
+            ..RunCommand::new(
+                cstring!(b"/bin/sh"),
+                vec![
+                    cstring!(b"sh"),
+                    cstring!(b"-c"),
+                    cstring!(b"\"$0\" -u && \"$0\" -g"),
+                    id,
+                ],
+            )
+        };
+        let (result, mut build_log) = call_perform_run_command(&action, &[]);
+        assert_matches!(result, Ok(Success{warnings: false, ..}));
+        let mut buf = String::new();
+        build_log.read_to_string(&mut buf).unwrap();
+        assert_eq!(buf, "1000\n100\n");
+    }
+
+    #[test]
+    fn uid_map_overlap()
+    {
+        let ok = [
+            IdMapping{inside: 0, outside: 1000, count: 10},
+            IdMapping{inside: 10, outside: 1010, count: 10},
+        ];
+        assert!(id_map_contents(&ok, 0).is_ok());
+
+        for bad in [
+            [ok[0], IdMapping{inside: 5,  outside: 2000, count: 10}],
+            [ok[0], IdMapping{inside: 20, outside: 1009, count: 10}],
+            [ok[0], IdMapping{inside: 20, outside: 2000, count: 0}],
+        ] {
+            assert!(id_map_contents(&bad, 0).is_err());
+        }
     }
 
     #[test]
@@ -854,33 +2249,173 @@ mod tests
     {
         let coreutils = CString::new(env!("SNOWFLAKE_COREUTILS")).unwrap();
         let action = RunCommand{
-            inputs: vec![],
-            outputs: Outputs::Outputs(vec![]),
-            program: coreutils.join(cstr!(b"bin/false")),
-            arguments: vec![cstring!(b"false")],
-            environment: vec![],
             timeout: Duration::from_millis(50),
-            warnings: None,
+            ..RunCommand::new(
+                coreutils.join(cstr!(b"bin/false")),
+                vec![cstring!(b"false")],
+            )
         };
         let (result, _) = call_perform_run_command(&action, &[]);
-        assert_matches!(result, Err(Error::ExitStatus(_)));
+        assert_matches!(result, Err(Error::Command{..}));
+    }
+
+    #[test]
+    fn unsuccessful_termination_log_tail()
+    {
+        let action = RunCommand{
+            timeout: Duration::from_millis(50),
+            log_tail_lines: 3,
+            ..RunCommand::new(
+                cstring!(b"/bin/sh"),
+                vec![
+                    cstring!(b"sh"),
+                    cstring!(b"-c"),
+                    cstring!(br#"
+                        printf 'line %s\n' 1 2 3 4 5
+                        echo oops >&2
+                        exit 1
+                    "#),
+                ],
+            )
+        };
+        let (result, mut build_log) = call_perform_run_command(&action, &[]);
+        assert_matches!(
+            result,
+            Err(Error::Command{status, log_tail})
+                if status.code() == Some(1)
+                && log_tail == b"line 4\nline 5\noops\n"
+        );
+        let mut buf = String::new();
+        build_log.read_to_string(&mut buf).unwrap();
+        assert_eq!(buf, "line 1\nline 2\nline 3\nline 4\nline 5\noops\n");
+    }
+
+    #[test]
+    fn read_log_tail_chunks()
+    {
+        let build_log = open(cstr!(b"."), O_RDWR | O_TMPFILE, 0o644).unwrap();
+        let mut file = File::from(build_log.try_clone().unwrap());
+
+        // Lines spanning multiple chunks, without a trailing newline.
+        let long_line = "x".repeat(5000);
+        write!(file, "first\n{long_line}\nsecond\nthird").unwrap();
+
+        let tail = |lines| read_log_tail(build_log.as_fd(), lines).unwrap();
+        assert_eq!(tail(0), b"");
+        assert_eq!(tail(1), b"third");
+        assert_eq!(tail(2), b"second\nthird");
+        assert_eq!(tail(3), format!("{long_line}\nsecond\nthird").as_bytes());
+        assert_eq!(tail(9), format!("first\n{long_line}\nsecond\nthird").as_bytes());
+    }
+
+    #[test]
+    fn read_log_tail_byte_cap()
+    {
+        let build_log = open(cstr!(b"."), O_RDWR | O_TMPFILE, 0o644).unwrap();
+        let mut file = File::from(build_log.try_clone().unwrap());
+
+        // A huge log without any newlines must not be read in full.
+        let huge_line = "x".repeat(1 << 20);
+        write!(file, "{huge_line}").unwrap();
+
+        let tail = |lines| read_log_tail(build_log.as_fd(), lines).unwrap();
+        let cap = MAX_LOG_TAIL_LINE_LENGTH as usize;
+        assert_eq!(tail(1), &huge_line.as_bytes()[.. cap]);
+        assert_eq!(tail(3), &huge_line.as_bytes()[.. 3 * cap]);
+    }
+
+    #[test]
+    fn working_directory()
+    {
+        let action = RunCommand{
+            working_directory: Some(PathBuf::from("foo/./bar")),
+            timeout: Duration::from_millis(50),
+            ..RunCommand::new(
+                cstring!(b"/bin/sh"),
+                vec![
+                    cstring!(b"sh"),
+                    cstring!(b"-c"),
+                    cstring!(b"pwd"),
+                ],
+            )
+        };
+        let (result, mut build_log) = call_perform_run_command(&action, &[]);
+        assert_matches!(result, Ok(Success{warnings: false, ..}));
+        let mut buf = String::new();
+        build_log.read_to_string(&mut buf).unwrap();
+        assert_eq!(buf, "/build/foo/bar\n");
+    }
+
+    #[test]
+    fn working_directory_input()
+    {
+        let source_root = TempDir::new(cstring!(b"/tmp/snowflake-test-XXXXXX")).unwrap();
+        let source_root_fd = Some(source_root.as_fd());
+        mkdirat(source_root_fd, cstr!(b"project"), 0o755).unwrap();
+        mkdirat(source_root_fd, cstr!(b"project/src"), 0o755).unwrap();
+        let file = openat(source_root_fd, cstr!(b"project/src/main.txt"),
+                          O_CREAT | O_WRONLY, 0o644).unwrap();
+        File::from(file).write_all(b"Hello, world!\n").unwrap();
+
+        let action = RunCommand{
+            inputs: vec![Basename::new(cstring!(b"project")).unwrap()],
+            working_directory: Some(PathBuf::from("project/src")),
+            timeout: Duration::from_millis(50),
+            ..RunCommand::new(
+                cstring!(b"/bin/sh"),
+                vec![
+                    cstring!(b"sh"),
+                    cstring!(b"-c"),
+                    cstring!(b"read line < main.txt && echo \"$line\""),
+                ],
+            )
+        };
+
+        let input_paths = [InputPath{
+            dirfd: source_root.as_fd(),
+            path: Cow::Borrowed(cstr!(b"project")),
+        }];
+
+        let (result, mut build_log) =
+            call_perform_run_command(&action, &input_paths);
+
+        assert_matches!(result, Ok(Success{warnings: false, ..}));
+        let mut buf = String::new();
+        build_log.read_to_string(&mut buf).unwrap();
+        assert_eq!(buf, "Hello, world!\n");
+    }
+
+    #[test]
+    fn working_directory_escape()
+    {
+        for working_directory in ["foo/../..", "/build/foo"] {
+            let action = RunCommand{
+                working_directory: Some(PathBuf::from(working_directory)),
+                timeout: Duration::from_millis(50),
+                ..RunCommand::new(
+                    cstring!(b"/bin/sh"),
+                    vec![cstring!(b"sh"), cstring!(b"-c"), cstring!(b"")],
+                )
+            };
+            let (result, _) = call_perform_run_command(&action, &[]);
+            assert_matches!(result, Err(Error::Unexpected(_)));
+        }
     }
 
     #[test]
     fn warnings()
     {
         let action = RunCommand{
-            inputs: vec![],
-            outputs: Outputs::Outputs(vec![]),
-            program: cstring!(b"/bin/sh"),
-            arguments: vec![
-                cstring!(b"sh"),
-                cstring!(b"-c"),
-                cstring!(b"echo hello; echo 'warning: boo'"),
-            ],
-            environment: vec![],
             timeout: Duration::from_millis(50),
             warnings: Some(Regex::new("^warning:").unwrap()),
+            ..RunCommand::new(
+                cstring!(b"/bin/sh"),
+                vec![
+                    cstring!(b"sh"),
+                    cstring!(b"-c"),
+                    cstring!(b"echo hello; echo 'warning: boo'"),
+                ],
+            )
         };
         let (result, _) = call_perform_run_command(&action, &[]);
         assert_matches!(result, Ok(Success{warnings: true, ..}));
@@ -0,0 +1,130 @@
+use std::collections::BTreeSet;
+
+/// Value of `seccomp_data.arch` for the native system call ABI.
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH_NATIVE: u32 = 0xC000003E;
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH_NATIVE: u32 = 0xC00000B7;
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+compile_error!("AUDIT_ARCH_NATIVE is not known for this architecture");
+
+/// Bit set in system call numbers of the x32 ABI.
+///
+/// x32 system calls report the same architecture as x86-64 ones,
+/// so they must be rejected separately.
+#[cfg(target_arch = "x86_64")]
+const X32_SYSCALL_BIT: u32 = 0x40000000;
+
+// Offsets of the fields of `struct seccomp_data`.
+const SECCOMP_DATA_NR:   u32 = 0;
+const SECCOMP_DATA_ARCH: u32 = 4;
+
+/// Which system calls a command may make.
+///
+/// The policy is compiled to a seccomp BPF program,
+/// which is installed right before the command is executed.
+/// Denied system calls fail with `EPERM`.
+/// System calls made through a foreign ABI kill the command.
+#[derive(Clone, Debug, Default)]
+pub struct SeccompPolicy
+{
+    denied: BTreeSet<libc::c_long>,
+}
+
+impl SeccompPolicy
+{
+    /// Create a policy that denies no system calls.
+    ///
+    /// [`RunCommand`][`crate::RunCommand`] does not install such a policy,
+    /// so commands may then also use foreign ABIs.
+    pub fn new() -> Self
+    {
+        Self::default()
+    }
+
+    /// Create a policy that denies system calls
+    /// which no build should ever need to make.
+    ///
+    /// These system calls inspect other processes,
+    /// change the mount table, change the system clock,
+    /// or otherwise modify the state of the kernel.
+    pub fn recommended() -> Self
+    {
+        Self::new()
+            .deny(libc::SYS_ptrace)
+            .deny(libc::SYS_process_vm_readv)
+            .deny(libc::SYS_process_vm_writev)
+            .deny(libc::SYS_mount)
+            .deny(libc::SYS_umount2)
+            .deny(libc::SYS_pivot_root)
+            .deny(libc::SYS_clock_settime)
+            .deny(libc::SYS_clock_adjtime)
+            .deny(libc::SYS_settimeofday)
+            .deny(libc::SYS_adjtimex)
+            .deny(libc::SYS_init_module)
+            .deny(libc::SYS_finit_module)
+            .deny(libc::SYS_delete_module)
+            .deny(libc::SYS_kexec_load)
+            .deny(libc::SYS_reboot)
+            .deny(libc::SYS_swapon)
+            .deny(libc::SYS_swapoff)
+            .deny(libc::SYS_bpf)
+            .deny(libc::SYS_perf_event_open)
+    }
+
+    /// Deny the system call with the given number.
+    pub fn deny(mut self, sysno: libc::c_long) -> Self
+    {
+        self.denied.insert(sysno);
+        self
+    }
+
+    /// The numbers of the denied system calls, in ascending order.
+    pub fn denied(&self) -> impl '_ + Iterator<Item=libc::c_long>
+    {
+        self.denied.iter().copied()
+    }
+
+    /// Compile the policy to a BPF program.
+    ///
+    /// The program can be installed with
+    /// [`prctl_set_seccomp_filter`][`os_ext::prctl_set_seccomp_filter`].
+    pub fn compile(&self) -> Vec<libc::sock_filter>
+    {
+        let stmt = |code, k| libc::sock_filter{code: code as u16, jt: 0, jf: 0, k};
+        let jump = |code, k, jt, jf| libc::sock_filter{code: code as u16, jt, jf, k};
+
+        let load    = libc::BPF_LD  | libc::BPF_W   | libc::BPF_ABS;
+        let jeq     = libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K;
+        let ret     = libc::BPF_RET | libc::BPF_K;
+        let kill    = libc::SECCOMP_RET_KILL_PROCESS;
+        let deny    = libc::SECCOMP_RET_ERRNO | libc::EPERM as u32;
+        let allow   = libc::SECCOMP_RET_ALLOW;
+
+        let mut program = vec![
+            // Kill the command if it uses a foreign ABI,
+            // as system call numbers differ between ABIs.
+            stmt(load, SECCOMP_DATA_ARCH),
+            jump(jeq, AUDIT_ARCH_NATIVE, 1, 0),
+            stmt(ret, kill),
+
+            stmt(load, SECCOMP_DATA_NR),
+        ];
+
+        #[cfg(target_arch = "x86_64")]
+        {
+            let jge = libc::BPF_JMP | libc::BPF_JGE | libc::BPF_K;
+            program.push(jump(jge, X32_SYSCALL_BIT, 0, 1));
+            program.push(stmt(ret, kill));
+        }
+
+        for sysno in self.denied() {
+            program.push(jump(jeq, sysno as u32, 0, 1));
+            program.push(stmt(ret, deny));
+        }
+
+        program.push(stmt(ret, allow));
+
+        program
+    }
+}
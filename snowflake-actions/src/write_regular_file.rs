@@ -1,12 +1,16 @@
 use {
     anyhow::Context,
-    os_ext::{O_CREAT, O_WRONLY, cstring, openat},
+    os_ext::{O_CREAT, O_RDONLY, O_WRONLY, cstring, open, openat, io::magic_link},
     snowflake_core::action::{
-        Action, InputPath, Outputs,
+        Action, Error, InputPath, Outputs,
         Perform, Result, Success,
     },
     snowflake_util::hash::{Blake3, Hash},
-    std::{fs::File, io::Write},
+    std::{
+        fs::File,
+        io::{self, Write, copy},
+        os::unix::io::{AsFd, OwnedFd},
+    },
 };
 
 /// Action that writes a regular file.
@@ -60,3 +64,195 @@ impl Action for WriteRegularFile
         h.finalize()
     }
 }
+
+/// Action that writes a regular file with content read from a file.
+///
+/// Unlike [`WriteRegularFile`], the content is never held in memory.
+/// It is read once when the action is created, to compute its hash,
+/// and once more when the action is performed, to copy it to the output.
+/// The source file must not be modified in the meantime.
+/// The copy is hashed as well, and if it does not match,
+/// performing the action fails rather than caching the wrong output.
+pub struct WriteRegularFileFrom
+{
+    source: OwnedFd,
+    content_hash: Hash,
+    executable: bool,
+}
+
+impl WriteRegularFileFrom
+{
+    /// Create the action, reading the source file to compute its hash.
+    ///
+    /// The source file is read from the start,
+    /// regardless of the file offset of `source`.
+    pub fn new(source: OwnedFd, executable: bool) -> io::Result<Self>
+    {
+        let mut h = Blake3::new();
+        copy(&mut reopen_source(&source)?, &mut h)?;
+        let content_hash = h.finalize();
+        Ok(Self{source, content_hash, executable})
+    }
+}
+
+impl Action for WriteRegularFileFrom
+{
+    fn inputs(&self) -> usize
+    {
+        0
+    }
+
+    fn outputs(&self) -> Outputs<usize>
+    {
+        Outputs::Outputs(1)
+    }
+
+    fn perform(&self, perform: &Perform, input_paths: &[InputPath]) -> Result
+    {
+        debug_assert_eq!(input_paths.len(), 0);
+        let output_path = cstring!(b"output");
+        let flags = O_CREAT | O_WRONLY;
+        let mode = if self.executable { 0o755 } else { 0o644 };
+        let mut source = reopen_source(&self.source)
+            .context("Open source file")?;
+        let file = openat(Some(perform.scratch), &output_path, flags, mode)
+            .context("Open regular file")?;
+        let mut writer = HashingWriter{
+            inner: File::from(file),
+            hash: Blake3::new(),
+        };
+        copy(&mut source, &mut writer)
+            .context("Copy source file to regular file")?;
+        if writer.hash.finalize() != self.content_hash {
+            let message = "Source file was modified after creating the action";
+            return Err(Error::from(anyhow::Error::msg(message)));
+        }
+        Ok(Success{output_paths: vec![output_path], warnings: false})
+    }
+
+    fn hash(&self, input_hashes: &[Hash]) -> Hash
+    {
+        // NOTE: See the manual chapter on avoiding hash collisions.
+
+        let Self{source, content_hash, executable} = self;
+
+        debug_assert_eq!(input_hashes.len(), 0);
+
+        // The content hash covers the content of the source.
+        let _ = source;
+
+        let mut h = Blake3::new();
+        h.put_str("WriteRegularFileFrom");
+        h.put_hash(*content_hash);
+        h.put_bool(*executable);
+        h.finalize()
+    }
+}
+
+/// Writer that hashes everything written to the inner writer.
+struct HashingWriter<W>
+{
+    inner: W,
+    hash: Blake3,
+}
+
+impl<W: Write> Write for HashingWriter<W>
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize>
+    {
+        let n = self.inner.write(buf)?;
+        self.hash.update(&buf[.. n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()>
+    {
+        self.inner.flush()
+    }
+}
+
+/// Open the source file anew, so that it has its own file offset.
+fn reopen_source(source: &OwnedFd) -> io::Result<File>
+{
+    open(&magic_link(source.as_fd()), O_RDONLY, 0).map(File::from)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use {
+        super::*,
+        os_ext::{O_RDWR, O_TMPFILE, cstr, temp_dir::TempDir},
+        snowflake_core::state::State,
+        snowflake_util::hash::hash_file_at,
+        std::{
+            assert_matches::assert_matches,
+            io::{Read, Seek},
+        },
+        uuid::Uuid,
+    };
+
+    #[test]
+    fn from_modified_file()
+    {
+        let state_dir = TempDir::new(cstring!(b"/tmp/snowflake-test-XXXXXX")).unwrap();
+        let state = State::open(state_dir.path()).unwrap();
+
+        let source = open(cstr!(b"/tmp"), O_RDWR | O_TMPFILE, 0o644).unwrap();
+        let mut source = File::from(source);
+        source.write_all(b"Hello!\n").unwrap();
+        let action = WriteRegularFileFrom::new(
+            source.try_clone().unwrap().into(),
+            false,
+        ).unwrap();
+
+        // Modify the source after the content hash was computed.
+        source.write_all(b"Goodbye!\n").unwrap();
+
+        let prepared = state.prepare_perform(Uuid::new_v4()).unwrap();
+        let result = action.perform(&prepared.perform(), &[]);
+        assert_matches!(result, Err(Error::Unexpected(_)));
+    }
+
+    #[test]
+    fn from_large_file()
+    {
+        let state_dir = TempDir::new(cstring!(b"/tmp/snowflake-test-XXXXXX")).unwrap();
+        let state = State::open(state_dir.path()).unwrap();
+        let build_id = Uuid::new_v4();
+
+        // Create a source file of several megabytes,
+        // and leave its offset at the end, which must not matter.
+        let content: Vec<u8> = (0 .. 5 << 20).map(|i| (i % 251) as u8).collect();
+        let source = open(cstr!(b"/tmp"), O_RDWR | O_TMPFILE, 0o644).unwrap();
+        let mut source = File::from(source);
+        source.write_all(&content).unwrap();
+        assert_eq!(source.stream_position().unwrap(), 5 << 20);
+
+        let action = WriteRegularFileFrom::new(source.into(), false).unwrap();
+        let expected = WriteRegularFileFrom{
+            source: open(cstr!(b"/dev/null"), O_RDONLY, 0).unwrap(),
+            content_hash: Blake3::new().update(&content).finalize(),
+            executable: false,
+        };
+        assert_eq!(action.hash(&[]), expected.hash(&[]));
+
+        let prepared = state.prepare_perform(build_id).unwrap();
+        let success = action.perform(&prepared.perform(), &[]).unwrap();
+
+        let scratch = Some(prepared.scratch.as_fd());
+        let output_path = &success.output_paths[0];
+        let file = openat(scratch, output_path, O_RDONLY, 0).unwrap();
+        let mut output = Vec::new();
+        File::from(file).read_to_end(&mut output).unwrap();
+        assert!(output == content);
+
+        // Performing again yields the same output.
+        let prepared = state.prepare_perform(build_id).unwrap();
+        let again = action.perform(&prepared.perform(), &[]).unwrap();
+        assert_eq!(
+            hash_file_at(Some(prepared.scratch.as_fd()), &again.output_paths[0]).unwrap(),
+            hash_file_at(scratch, output_path).unwrap(),
+        );
+    }
+}
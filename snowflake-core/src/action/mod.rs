@@ -1,14 +1,19 @@
 //! Describing and performing actions.
 
-pub use self::{graph::*, outputs::*};
+pub use {
+    self::{graph::*, outputs::*},
+    snowflake_util::hash::FileType as InputFileType,
+};
 
 use {
-    snowflake_util::hash::Hash,
+    os_ext::{AT_SYMLINK_NOFOLLOW, fstatat},
+    snowflake_util::{basename::Basename, hash::Hash},
     std::{
         borrow::Cow,
         ffi::{CStr, CString},
+        io,
         os::unix::io::BorrowedFd,
-        process::ExitStatusError,
+        process::ExitStatus,
         time::Duration,
     },
     thiserror::Error,
@@ -82,6 +87,20 @@ pub struct InputPath<'a, 'b>
     pub path: Cow<'b, CStr>,
 }
 
+/// Find the type of file an input is.
+///
+/// Symbolic links are not followed, as the input is the link itself.
+/// Actions should use this function rather than inspecting the mode,
+/// so that they classify inputs the same way the driver hashes them.
+/// Inputs of type [`Other`][`InputFileType::Other`] cannot be hashed,
+/// so the driver never passes them to actions.
+pub fn input_file_type(dirfd: Option<BorrowedFd>, path: &CStr)
+    -> io::Result<InputFileType>
+{
+    let statbuf = fstatat(dirfd, path, AT_SYMLINK_NOFOLLOW)?;
+    Ok(InputFileType::from_mode(statbuf.st_mode))
+}
+
 /// Result of performing an action.
 pub type Result =
     std::result::Result<Success, Error>;
@@ -115,12 +134,74 @@ pub struct Success
 #[derive(Debug, Error)]
 pub enum Error
 {
-    #[error("Timeout after {0:?}")]
-    Timeout(Duration),
+    /// The command did not terminate in time and was killed.
+    ///
+    /// `killed` tells whether the command was confirmed to have been
+    /// terminated by the kill, rather than having exited by itself.
+    /// `log_bytes` is the size of the build log after it was killed.
+    #[error("Timeout after {after:?} with {log_bytes} bytes of output (killed: {killed})")]
+    Timeout{after: Duration, killed: bool, log_bytes: u64},
 
-    #[error("{0}")]
-    ExitStatus(#[from] ExitStatusError),
+    /// The build log grew larger than the given number of bytes.
+    #[error("Build log exceeded {0} bytes")]
+    LogTooLarge(u64),
+
+    /// The command terminated unsuccessfully.
+    ///
+    /// The last few lines of the build log are included,
+    /// so that the failure can be reported without reading the build log.
+    #[error("Command failed with {status}")]
+    Command{status: ExitStatus, log_tail: Vec<u8>},
+
+    /// A declared output was not created by the action.
+    #[error("Output {basename:?} was not created")]
+    MissingOutput{basename: Basename<CString>},
+
+    /// A declared output is not a regular file, directory, or symbolic link.
+    #[error("Output {basename:?} is of an unsupported file type")]
+    UnsupportedOutputType{basename: Basename<CString>},
 
     #[error("Unexpected error: {0}")]
     Unexpected(#[from] anyhow::Error),
 }
+
+#[cfg(test)]
+mod tests
+{
+    use {
+        super::*,
+        os_ext::{
+            S_IFIFO, S_IFREG, cstr, cstring, mkdirat, mknodat, symlinkat,
+            cstr::CStrExt,
+            temp_dir::TempDir,
+        },
+        std::{
+            ffi::OsStr,
+            os::unix::{ffi::OsStrExt, io::AsFd, net::UnixListener},
+        },
+    };
+
+    #[test]
+    fn input_file_types()
+    {
+        let dir = TempDir::new(cstring!(b"/tmp/snowflake-test-XXXXXX")).unwrap();
+        let dirfd = Some(dir.as_fd());
+
+        mknodat(dirfd, cstr!(b"regular"), S_IFREG | 0o644, 0).unwrap();
+        mkdirat(dirfd, cstr!(b"directory"), 0o755).unwrap();
+        symlinkat(cstr!(b"directory"), dirfd, cstr!(b"symlink")).unwrap();
+        mknodat(dirfd, cstr!(b"fifo"), S_IFIFO | 0o644, 0).unwrap();
+        let socket = dir.path().join(cstr!(b"socket"));
+        let _socket = UnixListener::bind(OsStr::from_bytes(socket.to_bytes())).unwrap();
+
+        let file_type = |path| input_file_type(dirfd, path).unwrap();
+        assert_eq!(file_type(cstr!(b"regular")),   InputFileType::Regular);
+        assert_eq!(file_type(cstr!(b"directory")), InputFileType::Directory);
+        assert_eq!(file_type(cstr!(b"symlink")),   InputFileType::Symlink);
+        assert_eq!(file_type(cstr!(b"fifo")),      InputFileType::Other);
+        assert_eq!(file_type(cstr!(b"socket")),    InputFileType::Other);
+
+        let err = input_file_type(dirfd, cstr!(b"missing")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+}
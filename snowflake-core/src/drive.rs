@@ -2,19 +2,21 @@
 
 use {
     crate::{
-        action::{self, Action, ActionGraph, Input, InputPath, Perform, Success},
+        action::{self, Action, ActionGraph, Input, InputPath, Success},
         label::ActionLabel,
-        state::{ActionCacheEntry, CacheOutputError, State},
+        state::{ActionCacheEntry, CacheOutputError, PreparedPerform, State},
     },
     anyhow::{Context as _},
-    os_ext::{O_RDWR, O_TMPFILE, cstr, openat},
     snowflake_util::hash::{Hash, hash_file_at},
     std::{
         borrow::Cow,
         collections::HashMap,
+        io,
         os::unix::io::{AsFd, BorrowedFd, OwnedFd},
+        time::{Duration, Instant},
     },
     thiserror::Error,
+    uuid::Uuid,
 };
 
 /// Parameters passed to the driver.
@@ -39,6 +41,9 @@ pub enum DriveError
     #[error("There is an action that depends on a missing action")]
     // TODO: Which actions?
     DanglingDependency,
+
+    #[error("Cannot look up action in caches: {0}")]
+    Cache(#[from] io::Error),
 }
 
 /// Error that occurs whilst building an action.
@@ -62,6 +67,10 @@ pub enum BuildError
 /// if it is found in the action cache, or
 /// if it is performed successfully and all outputs are able to be cached.
 /// This type describes those scenarios, as well as possible failure cases.
+///
+/// `duration` is the time spent performing the action,
+/// and `cache_duration` is the time spent hashing the action,
+/// looking it up in the action cache, and moving its results to the caches.
 #[allow(missing_docs)]
 #[derive(Debug)]
 pub enum Outcome<'a>
@@ -70,32 +79,225 @@ pub enum Outcome<'a>
     Success{
         cache_entry: ActionCacheEntry,
         cache_hit: bool,
+        duration: Duration,
+        cache_duration: Duration,
     },
 
     /// Performing the action failed because of an error.
     Failed{
         build_log: Option<Hash>,
         error: BuildError,
+        duration: Duration,
+        cache_duration: Duration,
     },
 
     /// The action was skipped because a transitive dependency failed.
     Skipped{failed_dependency: &'a ActionLabel},
 }
 
+/// The outcomes of building all actions in an action graph.
+#[derive(Debug)]
+pub struct BuildReport<'a>
+{
+    /// The outcome of each action.
+    pub outcomes: HashMap<&'a ActionLabel, Outcome<'a>>,
+
+    /// Time from the start to the end of the build.
+    pub wall_duration: Duration,
+
+    /// Total time spent on performing and caching actions.
+    pub action_duration: Duration,
+
+    /// Why the scratch directories of the build could not be removed.
+    ///
+    /// This does not affect the outcomes, as the outputs of the actions
+    /// were moved to the output cache before the removal was attempted.
+    pub clean_scratch_error: Option<io::Error>,
+}
+
+/// Receives events from the driver as it builds actions.
+///
+/// This allows reporting progress without the driver
+/// having to know how progress is to be presented.
+/// Events may be delivered from any thread, hence the [`Sync`] bound.
+/// Every method does nothing by default.
+pub trait BuildObserver: Sync
+{
+    /// The action was not found in the action cache
+    /// and is about to be performed.
+    fn action_started(&self, _label: &ActionLabel)
+    {
+    }
+
+    /// The action was found in the action cache
+    /// and will not be performed.
+    fn action_cached(&self, _label: &ActionLabel)
+    {
+    }
+
+    /// The outcome of building the action is known.
+    ///
+    /// This is called for every action,
+    /// including those that were cached or skipped.
+    fn action_finished(&self, _label: &ActionLabel, _outcome: &Outcome)
+    {
+    }
+
+    /// Every action has been built.
+    fn build_finished(&self, _report: &BuildReport)
+    {
+    }
+}
+
+/// Observer that ignores all events.
+impl BuildObserver for ()
+{
+}
+
 /// Build all actions in an action graph.
-pub fn drive<'a>(context: &Context, graph: &'a ActionGraph)
-    -> Result<HashMap<&'a ActionLabel, Outcome<'a>>, DriveError>
+pub fn drive<'a>(
+    context:  &Context,
+    graph:    &'a ActionGraph,
+    observer: &dyn BuildObserver,
+) -> Result<BuildReport<'a>, DriveError>
 {
+    let start = Instant::now();
+
     let linear = prepare(graph)?;
 
+    // Scratch directories are grouped by build,
+    // so that they can be removed together afterwards.
+    let build_id = Uuid::new_v4();
+
     let mut outcomes = HashMap::new();
 
     for (label, action, inputs) in linear {
-        let outcome = build(context, &outcomes, action, inputs);
+        let builder = Builder{context, observer, build_id, timing: Timing::default()};
+        let outcome = builder.build(&outcomes, label, action, inputs);
+        observer.action_finished(label, &outcome);
         outcomes.insert(label, outcome);
     }
 
-    Ok(outcomes)
+    // Outputs have been moved to the output cache,
+    // so the scratch directories are no longer needed.
+    let clean_scratch_error = context.state.clean_build_scratch(build_id).err();
+
+    let action_duration =
+        outcomes.values()
+        .map(|outcome| match outcome {
+            Outcome::Success{duration, cache_duration, ..} |
+            Outcome::Failed{duration, cache_duration, ..} =>
+                *duration + *cache_duration,
+            Outcome::Skipped{..} =>
+                Duration::ZERO,
+        })
+        .sum();
+
+    let wall_duration = start.elapsed();
+
+    let report = BuildReport{outcomes, wall_duration, action_duration,
+                             clean_scratch_error};
+    observer.build_finished(&report);
+
+    Ok(report)
+}
+
+/// Which actions [`drive`] would perform and which it would find cached.
+#[derive(Debug)]
+pub struct BuildPlan<'a>
+{
+    /// Actions that are found in the action cache.
+    pub cached: Vec<&'a ActionLabel>,
+
+    /// Actions that would be performed.
+    ///
+    /// This includes every action that transitively depends
+    /// on an action that would be performed,
+    /// as its hash cannot be known without the outputs of its dependencies.
+    /// Such actions may still turn out to be cached once built.
+    /// This also includes actions whose hash cannot be computed,
+    /// for example because of a missing input;
+    /// the error is reported when the action is built.
+    pub to_run: Vec<&'a ActionLabel>,
+}
+
+/// Find out which actions in an action graph [`drive`] would perform.
+///
+/// No actions are performed and no caches are modified.
+/// Only the local caches are consulted, as fetching an action
+/// from the remote cache would insert it into the local caches.
+/// Errors from looking up actions in the caches are returned,
+/// rather than counting those actions as to be performed.
+pub fn plan<'a>(context: &Context, graph: &'a ActionGraph)
+    -> Result<BuildPlan<'a>, DriveError>
+{
+    let linear = prepare(graph)?;
+
+    let mut plan = BuildPlan{cached: Vec::new(), to_run: Vec::new()};
+
+    // Used to find the outputs of cached dependencies,
+    // and to find dependents of actions to be performed.
+    let mut planned = HashMap::new();
+
+    for (label, action, inputs) in linear {
+        let cache_entry = check_action_cache_without_building(
+            context, &planned, action, inputs)?;
+        match cache_entry {
+            Some(cache_entry) => {
+                plan.cached.push(label);
+                planned.insert(label, Planned::Cached(cache_entry));
+            },
+            None => {
+                plan.to_run.push(label);
+                planned.insert(label, Planned::ToRun);
+            },
+        }
+    }
+
+    Ok(plan)
+}
+
+/// What [`plan`] found out about an action.
+enum Planned
+{
+    /// The action was found in the action cache.
+    Cached(ActionCacheEntry),
+
+    /// The action would be performed.
+    ToRun,
+}
+
+/// Look up an action in the action cache, if its hash can be computed.
+fn check_action_cache_without_building<'a>(
+    context: &Context,
+    planned: &HashMap<&ActionLabel, Planned>,
+    action:  &dyn Action,
+    inputs:  &'a [Input],
+) -> io::Result<Option<ActionCacheEntry>>
+{
+    let dependency = |label: &'a ActionLabel| {
+        let planned = planned.get(label)
+            .expect("Action should have been planned before");
+        match planned {
+            Planned::Cached(cache_entry) => Ok(cache_entry),
+            Planned::ToRun => Err(label),
+        }
+    };
+
+    // If a dependency would be performed, its outputs are not yet known.
+    let input_paths = match collect_input_paths(context, inputs, dependency)? {
+        Ok(input_paths) => input_paths,
+        Err(_) => return Ok(None),
+    };
+
+    // If the hash cannot be computed, the action would fail,
+    // and the error is reported when the action is built.
+    let action_hash = match compute_action_hash(action, &input_paths) {
+        Ok((action_hash, _)) => action_hash,
+        Err(_) => return Ok(None),
+    };
+
+    context.state.cached_action(action_hash)
 }
 
 /// Topologically sort the action graph.
@@ -140,76 +342,157 @@ fn prepare(graph: &ActionGraph)
     Ok(linear)
 }
 
-/// Build an action.
-fn build<'a>(
-    context:  &Context,
-    outcomes: &HashMap<&ActionLabel, Outcome<'a>>,
-    action:   &dyn Action,
-    inputs:   &'a [Input],
-) -> Outcome<'a>
+/// Everything needed for building an action, besides the action itself.
+struct Builder<'a, 'b>
 {
-    match build_inner(context, outcomes, action, inputs) {
-        Ok(outcome) => outcome,
-        Err(error) => Outcome::Failed{build_log: None, error},
+    context:  &'a Context<'b>,
+    observer: &'a dyn BuildObserver,
+    build_id: Uuid,
+    timing:   Timing,
+}
+
+impl Builder<'_, '_>
+{
+    /// Build an action.
+    fn build<'a>(
+        mut self,
+        outcomes: &HashMap<&ActionLabel, Outcome<'a>>,
+        label:    &ActionLabel,
+        action:   &dyn Action,
+        inputs:   &'a [Input],
+    ) -> Outcome<'a>
+    {
+        let outcome = self.build_inner(outcomes, label, action, inputs);
+        match outcome {
+            Ok(outcome) => outcome,
+            Err(error) => Outcome::Failed{
+                build_log: None,
+                error,
+                duration: self.timing.perform,
+                cache_duration: self.timing.cache,
+            },
+        }
+    }
+
+    fn build_inner<'a>(
+        &mut self,
+        outcomes: &HashMap<&ActionLabel, Outcome<'a>>,
+        label:    &ActionLabel,
+        action:   &dyn Action,
+        inputs:   &'a [Input],
+    ) -> Result<Outcome<'a>, BuildError>
+    {
+        let (context, observer, build_id) = (self.context, self.observer, self.build_id);
+        let timing = &mut self.timing;
+
+        let dependency = |label: &'a ActionLabel| {
+            let outcome = outcomes.get(label)
+                .expect("Action should have been built before");
+            match outcome {
+                Outcome::Success{cache_entry, ..} => Ok(cache_entry),
+                Outcome::Failed{..} => Err(label),
+                Outcome::Skipped{failed_dependency} => Err(*failed_dependency),
+            }
+        };
+        let input_paths = collect_input_paths(context, inputs, dependency)      .with_context(|| "Retrieve dependency from output cache")?;
+        let input_paths = match input_paths {
+            Ok(input_paths) => input_paths,
+            Err(fd) => return Ok(Outcome::Skipped{failed_dependency: fd}),
+        };
+        let (action_hash, input_hashes) = timing.cache(|| compute_action_hash(action, &input_paths))?;
+        if let Some(cache_entry) = timing.cache(|| check_action_cache(context, build_id, action_hash))? {
+            observer.action_cached(label);
+            return Ok(Outcome::Success{
+                cache_entry,
+                cache_hit: true,
+                duration: timing.perform,
+                cache_duration: timing.cache,
+            });
+        }
+        observer.action_started(label);
+        let prepared = context.state.prepare_perform(build_id)                  .with_context(|| "Create build log and scratch directory")?;
+        let result = timing.perform(|| action.perform(&prepared.perform(), &input_paths));
+        let PreparedPerform{build_log, scratch} = prepared;
+        let build_log =
+            timing.cache(|| context.state.cache_build_log(build_log))           .with_context(|| "Move build log to output cache")?;
+        match result {
+            Ok(success) => {
+                let cache_entry = timing.cache(|| cache_action(context, action, action_hash, input_hashes, build_log, &scratch, &success))?;
+                Ok(Outcome::Success{
+                    cache_entry,
+                    cache_hit: false,
+                    duration: timing.perform,
+                    cache_duration: timing.cache,
+                })
+            },
+            Err(error) => Ok(Outcome::Failed{
+                build_log: Some(build_log),
+                error: error.into(),
+                duration: timing.perform,
+                cache_duration: timing.cache,
+            }),
+        }
     }
 }
 
-fn build_inner<'a>(
-    context:  &Context,
-    outcomes: &HashMap<&ActionLabel, Outcome<'a>>,
-    action:   &dyn Action,
-    inputs:   &'a [Input],
-) -> Result<Outcome<'a>, BuildError>
+/// Time spent on building an action so far.
+#[derive(Default)]
+struct Timing
 {
-    let input_paths = collect_input_paths(context, outcomes, inputs)?;
-    let input_paths = match input_paths {
-        Ok(input_paths) => input_paths,
-        Err(fd) => return Ok(Outcome::Skipped{failed_dependency: fd}),
-    };
-    let action_hash = compute_action_hash(action, &input_paths)?;
-    if let Some(cache_entry) = check_action_cache(context, action_hash)? {
-        return Ok(Outcome::Success{cache_entry, cache_hit: true});
+    perform: Duration,
+    cache: Duration,
+}
+
+impl Timing
+{
+    /// Call `f` and add the time it took to the perform time.
+    fn perform<T>(&mut self, f: impl FnOnce() -> T) -> T
+    {
+        measure(&mut self.perform, f)
     }
-    let build_log = create_build_log(context)?;
-    let scratch = context.state.new_scratch_dir()                               .with_context(|| "Create scratch directory")?;
-    let result = perform_action(action, &input_paths, &build_log, &scratch);
-    let build_log = context.state.cache_build_log(build_log)                    .with_context(|| "Move build log to output cache")?;
-    match result {
-        Ok(success) => cache_action(context, action, action_hash, build_log, &scratch, &success),
-        Err(error) => Ok(Outcome::Failed{build_log: Some(build_log), error: error.into()}),
+
+    /// Call `f` and add the time it took to the cache time.
+    fn cache<T>(&mut self, f: impl FnOnce() -> T) -> T
+    {
+        measure(&mut self.cache, f)
     }
 }
 
+fn measure<T>(total: &mut Duration, f: impl FnOnce() -> T) -> T
+{
+    let start = Instant::now();
+    let result = f();
+    *total += start.elapsed();
+    result
+}
+
 /// Compute the path of each input.
 ///
+/// `dependency` returns the cache entry of a built dependency,
+/// or the dependency that failed if it could not be built.
 /// If inputs are missing due to unfortunate outcomes of dependencies,
 /// this function returns early with the dependency that failed.
-fn collect_input_paths<'a, 'b>(
-    context:  &'a Context,
-    outcomes: &HashMap<&ActionLabel, Outcome<'b>>,
-    inputs:   &'b [Input],
-) -> Result<Result<Vec<InputPath<'a, 'b>>, &'b ActionLabel>, BuildError>
+fn collect_input_paths<'a, 'b, 'c>(
+    context:    &'a Context,
+    inputs:     &'b [Input],
+    dependency: impl Fn(&'b ActionLabel)
+                    -> Result<&'c ActionCacheEntry, &'b ActionLabel>,
+) -> io::Result<Result<Vec<InputPath<'a, 'b>>, &'b ActionLabel>>
 {
     let mut input_paths = Vec::with_capacity(inputs.len());
 
     for input in inputs {
         match input {
             Input::Dependency(label) => {
-                let outcome = outcomes.get(&label.action)
-                    .expect("Action should have been built before");
-                match outcome {
-                    Outcome::Success{cache_entry, ..} => {
-                        let hash = cache_entry.outputs.get(label.output)
-                            .expect("Action refers to non-existent output");
-                        let (dirfd, path) = context.state.cached_output(*hash)  .with_context(|| "Retrieve dependency from output cache")?;
-                        let path = Cow::Owned(path);
-                        input_paths.push(InputPath{dirfd, path});
-                    },
-                    Outcome::Failed{..} =>
-                        return Ok(Err(&label.action)),
-                    Outcome::Skipped{failed_dependency} =>
-                        return Ok(Err(failed_dependency)),
-                }
+                let cache_entry = match dependency(&label.action) {
+                    Ok(cache_entry) => cache_entry,
+                    Err(failed_dependency) => return Ok(Err(failed_dependency)),
+                };
+                let hash = cache_entry.outputs.get(label.output)
+                    .expect("Action refers to non-existent output");
+                let (dirfd, path) = context.state.cached_output(*hash)?;
+                let path = Cow::Owned(path);
+                input_paths.push(InputPath{dirfd, path});
             },
             Input::StaticFile(path) => {
                 let dirfd = context.source_root;
@@ -223,8 +506,11 @@ fn collect_input_paths<'a, 'b>(
 }
 
 /// Compute the hash of an action, which is its key into the action cache.
+///
+/// The hashes of the inputs are returned as well,
+/// so that they can be recorded in the action cache entry.
 fn compute_action_hash(action: &dyn Action, input_paths: &[InputPath])
-    -> Result<Hash, BuildError>
+    -> Result<(Hash, Vec<Hash>), BuildError>
 {
     let mut input_hashes = Vec::with_capacity(input_paths.len());
 
@@ -233,55 +519,39 @@ fn compute_action_hash(action: &dyn Action, input_paths: &[InputPath])
         input_hashes.push(hash);
     }
 
-    Ok(action.hash(&input_hashes))
+    Ok((action.hash(&input_hashes), input_hashes))
 }
 
 /// Look up the action in the action cache, in order to skip the build.
-fn check_action_cache(context: &Context, action_hash: Hash)
+fn check_action_cache(context: &Context, build_id: Uuid, action_hash: Hash)
     -> Result<Option<ActionCacheEntry>, BuildError>
 {
-    let cache_entry = context.state.cached_action(action_hash)                  .with_context(|| "Look up action in action cache")?;
+    let cache_entry = context.state.fetch_action(action_hash, build_id)         .with_context(|| "Look up action in action cache")?;
     Ok(cache_entry)
 }
 
-/// Create the file that will store the build log.
-fn create_build_log(context: &Context) -> Result<OwnedFd, BuildError>
-{
-    let state_dir = context.state.as_fd();
-    let file = openat(Some(state_dir), cstr!(b"."), O_TMPFILE | O_RDWR, 0o644)  .with_context(|| "Create build log")?;
-    Ok(file)
-}
-
-/// Perform the action.
-fn perform_action(
-    action: &dyn Action,
-    input_paths: &[InputPath],
-    build_log: &OwnedFd,
-    scratch: &OwnedFd,
-) -> action::Result
-{
-    let perform = Perform{
-        build_log: build_log.as_fd(),
-        scratch: scratch.as_fd(),
-    };
-    action.perform(&perform, input_paths)
-}
-
 /// Insert the outputs and action into the caches.
-fn cache_action<'a>(
-    context:     &Context,
-    action:      &dyn Action,
-    action_hash: Hash,
-    build_log:   Hash,
-    scratch:     &OwnedFd,
-    success:     &Success,
-) -> Result<Outcome<'a>, BuildError>
+fn cache_action(
+    context:      &Context,
+    action:       &dyn Action,
+    action_hash:  Hash,
+    input_hashes: Vec<Hash>,
+    build_log:    Hash,
+    scratch:      &OwnedFd,
+    success:      &Success,
+) -> Result<ActionCacheEntry, BuildError>
 {
     let outputs = cache_outputs(context, action, scratch, success)?;
     let warnings = success.warnings;
-    let cache_entry = ActionCacheEntry{build_log, outputs, warnings};
+    let cache_entry = ActionCacheEntry{
+        version: ActionCacheEntry::VERSION,
+        build_log,
+        outputs,
+        inputs: input_hashes,
+        warnings,
+    };
     context.state.cache_action(action_hash, &cache_entry)                       .with_context(|| "Insert action into action cache")?;
-    Ok(Outcome::Success{cache_entry, cache_hit: false})
+    Ok(cache_entry)
 }
 
 /// Move every output to the output cache and return their hashes.
@@ -311,3 +581,290 @@ fn cache_outputs(
 
     Ok(output_hashes)
 }
+
+#[cfg(test)]
+mod tests
+{
+    use {
+        super::*,
+        crate::{action::{Outputs, Perform}, label::ActionOutputLabel},
+        os_ext::{
+            O_CREAT, O_DIRECTORY, O_PATH, O_WRONLY, S_IFIFO,
+            cstr, cstring, mknodat, open, openat, temp_dir::TempDir,
+        },
+        snowflake_util::hash::Blake3,
+        std::{assert_matches::assert_matches, collections::HashSet, fs, sync::Mutex},
+    };
+
+    /// State directory and source root for use by a test.
+    struct TestContext
+    {
+        // Fields are dropped in order, so the state
+        // must be closed before its directory is removed.
+        state: State,
+        source_root: OwnedFd,
+        state_dir: TempDir,
+    }
+
+    impl TestContext
+    {
+        fn context(&self) -> Context
+        {
+            Context{state: &self.state, source_root: self.source_root.as_fd()}
+        }
+    }
+
+    /// Create a fresh state directory,
+    /// with the current directory as the source root.
+    fn test_context() -> TestContext
+    {
+        let state_dir = TempDir::new(cstring!(b"/tmp/snowflake-test-XXXXXX")).unwrap();
+        let state = State::open(state_dir.path()).unwrap();
+        let source_root = open(cstr!(b"."), O_DIRECTORY | O_PATH, 0).unwrap();
+        TestContext{state, source_root, state_dir}
+    }
+
+    /// Action that creates an empty file as its only output.
+    struct Touch
+    {
+        inputs: usize,
+        salt: u8,
+        sleep: Duration,
+    }
+
+    impl Action for Touch
+    {
+        fn inputs(&self) -> usize
+        {
+            self.inputs
+        }
+
+        fn outputs(&self) -> Outputs<usize>
+        {
+            Outputs::Outputs(1)
+        }
+
+        fn perform(&self, perform: &Perform, _input_paths: &[InputPath])
+            -> action::Result
+        {
+            std::thread::sleep(self.sleep);
+            let scratch = Some(perform.scratch);
+            openat(scratch, cstr!(b"output"), O_CREAT | O_WRONLY, 0o644)
+                .map_err(anyhow::Error::from)?;
+            Ok(Success{output_paths: vec![cstring!(b"output")], warnings: false})
+        }
+
+        fn hash(&self, input_hashes: &[Hash]) -> Hash
+        {
+            let mut h = Blake3::new();
+            h.put_u8(self.salt);
+            h.put_slice(input_hashes, |h, i| h.put_hash(*i));
+            h.finalize()
+        }
+    }
+
+    /// Create an action graph with the given actions,
+    /// each of which may depend on the only output of another action.
+    fn graph(actions: &[(usize, Option<usize>)]) -> ActionGraph
+    {
+        let actions =
+            actions.iter()
+            .map(|&(label, dependency)| {
+                let inputs: Vec<Input> =
+                    dependency.into_iter()
+                    .map(|d| ActionOutputLabel{
+                        action: ActionLabel{action: d},
+                        output: 0,
+                    })
+                    .map(Input::Dependency)
+                    .collect();
+                let touch = Touch{
+                    inputs: inputs.len(),
+                    salt: label as u8,
+                    sleep: Duration::ZERO,
+                };
+                let action: Box<dyn Action> = Box::new(touch);
+                (ActionLabel{action: label}, (action, inputs))
+            })
+            .collect();
+        ActionGraph{actions, artifacts: HashSet::new()}
+    }
+
+    #[test]
+    fn plan_partially_cached()
+    {
+        let fixture = test_context();
+        let context = fixture.context();
+
+        // Build actions 0 and 1, so that they are cached.
+        let partial_graph = graph(&[(0, None), (1, Some(0))]);
+        let report = drive(&context, &partial_graph, &()).unwrap();
+        assert!(report.outcomes.values().all(|o| matches!(o, Outcome::Success{..})));
+
+        // Action 2 is not cached, so neither is action 3 which depends on it.
+        let graph = graph(&[(0, None), (1, Some(0)), (2, None), (3, Some(2))]);
+        let plan = plan(&context, &graph).unwrap();
+        let labels = |labels: Vec<&ActionLabel>| {
+            let mut labels: Vec<usize> = labels.iter().map(|l| l.action).collect();
+            labels.sort();
+            labels
+        };
+        assert_eq!(labels(plan.cached), [0, 1]);
+        assert_eq!(labels(plan.to_run), [2, 3]);
+    }
+
+    /// Observer that records the events it receives.
+    #[derive(Default)]
+    struct Recorder
+    {
+        events: Mutex<Vec<(&'static str, usize)>>,
+    }
+
+    impl BuildObserver for Recorder
+    {
+        fn action_started(&self, label: &ActionLabel)
+        {
+            self.events.lock().unwrap().push(("started", label.action));
+        }
+
+        fn action_cached(&self, label: &ActionLabel)
+        {
+            self.events.lock().unwrap().push(("cached", label.action));
+        }
+
+        fn action_finished(&self, label: &ActionLabel, _outcome: &Outcome)
+        {
+            self.events.lock().unwrap().push(("finished", label.action));
+        }
+
+        fn build_finished(&self, report: &BuildReport)
+        {
+            self.events.lock().unwrap().push(("build finished", report.outcomes.len()));
+        }
+    }
+
+    #[test]
+    fn observer_events()
+    {
+        let fixture = test_context();
+        let context = fixture.context();
+
+        // Action 1 depends on action 0, so action 0 is built first.
+        let graph = graph(&[(1, Some(0)), (0, None)]);
+
+        let recorder = Recorder::default();
+        drive(&context, &graph, &recorder).unwrap();
+        assert_eq!(
+            recorder.events.into_inner().unwrap(),
+            [("started", 0), ("finished", 0),
+             ("started", 1), ("finished", 1),
+             ("build finished", 2)],
+        );
+
+        // The second time around, both actions are cached.
+        let recorder = Recorder::default();
+        drive(&context, &graph, &recorder).unwrap();
+        assert_eq!(
+            recorder.events.into_inner().unwrap(),
+            [("cached", 0), ("finished", 0),
+             ("cached", 1), ("finished", 1),
+             ("build finished", 2)],
+        );
+    }
+
+    #[test]
+    fn input_hashes_recorded()
+    {
+        let fixture = test_context();
+        let context = fixture.context();
+
+        let graph = graph(&[(0, None), (1, Some(0))]);
+        let report = drive(&context, &graph, &()).unwrap();
+        let cache_entry = |action| match &report.outcomes[&ActionLabel{action}] {
+            Outcome::Success{cache_entry, ..} => cache_entry.clone(),
+            outcome => panic!("{outcome:?}"),
+        };
+
+        // The input of action 1 is the output of action 0.
+        let (entry_0, entry_1) = (cache_entry(0), cache_entry(1));
+        assert!(entry_0.inputs.is_empty());
+        assert_eq!(entry_1.inputs, entry_0.outputs);
+
+        // Cache hits return the recorded inputs too.
+        let report = drive(&context, &graph, &()).unwrap();
+        let outcome = &report.outcomes[&ActionLabel{action: 1}];
+        assert_matches!(
+            outcome,
+            Outcome::Success{cache_entry, cache_hit: true, ..}
+                if cache_entry.inputs == entry_0.outputs
+        );
+    }
+
+    #[test]
+    fn unsupported_input_file_type()
+    {
+        let fixture = test_context();
+        let source_root = TempDir::new(cstring!(b"/tmp/snowflake-test-XXXXXX")).unwrap();
+        mknodat(Some(source_root.as_fd()), cstr!(b"fifo"), S_IFIFO | 0o644, 0).unwrap();
+        let context = Context{source_root: source_root.as_fd(), ..fixture.context()};
+
+        // The input cannot be hashed, which fails the action.
+        let touch = Touch{inputs: 1, salt: 0, sleep: Duration::ZERO};
+        let input = Input::StaticFile(cstring!(b"fifo"));
+        let graph = ActionGraph{
+            actions: [
+                (ActionLabel{action: 0}, (Box::new(touch) as Box<dyn Action>, vec![input])),
+            ].into_iter().collect(),
+            artifacts: HashSet::new(),
+        };
+
+        let report = drive(&context, &graph, &()).unwrap();
+        let outcome = &report.outcomes[&ActionLabel{action: 0}];
+        assert_matches!(outcome, Outcome::Failed{build_log: None, ..});
+    }
+
+    #[test]
+    fn scratch_cleaned()
+    {
+        let fixture = test_context();
+        let context = fixture.context();
+
+        let graph = graph(&[(0, None), (1, Some(0))]);
+        let report = drive(&context, &graph, &()).unwrap();
+        assert!(report.clean_scratch_error.is_none());
+
+        // The scratch directories of the build are gone.
+        let state_dir = fixture.state_dir.path().to_str().unwrap();
+        let scratches = fs::read_dir(format!("{state_dir}/scratches")).unwrap();
+        for entry in scratches {
+            assert!(!entry.unwrap().file_type().unwrap().is_dir());
+        }
+    }
+
+    #[test]
+    fn timing()
+    {
+        let fixture = test_context();
+        let context = fixture.context();
+
+        let sleep = Duration::from_millis(50);
+        let touch = Touch{inputs: 0, salt: 0, sleep};
+        let graph = ActionGraph{
+            actions: [
+                (ActionLabel{action: 0}, (Box::new(touch) as Box<dyn Action>, vec![])),
+            ].into_iter().collect(),
+            artifacts: HashSet::new(),
+        };
+
+        let report = drive(&context, &graph, &()).unwrap();
+        let outcome = &report.outcomes[&ActionLabel{action: 0}];
+        let (duration, cache_duration) = match outcome {
+            Outcome::Success{duration, cache_duration, ..} =>
+                (duration, cache_duration),
+            _ => panic!("{outcome:?}"),
+        };
+        assert!(*duration >= sleep);
+        assert_eq!(report.action_duration, *duration + *cache_duration);
+        assert!(report.wall_duration >= report.action_duration);
+    }
+}
@@ -5,9 +5,9 @@
 #![doc(html_logo_url = "/snowflake-manual/_static/logo.svg")]
 #![feature(assert_matches)]
 #![feature(concat_bytes)]
-#![feature(exit_status_error)]
 #![feature(io_error_other)]
 #![feature(io_safety)]
+#![feature(let_else)]
 #![feature(once_cell)]
 #![feature(type_ascription)]
 #![warn(missing_docs)]
@@ -171,10 +171,18 @@ mod tests
     use {
         super::*,
         os_ext::{
-            S_IFIFO, S_ISUID,
-            cstr, cstring, linkat, mkdirat, mkdtemp, mknodat,
+            AT_SYMLINK_NOFOLLOW, O_CREAT, O_WRONLY, S_IFIFO, S_ISUID,
+            cstr, cstring, fstatat, linkat, mkdirat, mknodat, openat,
+            readlinkat, symlinkat,
+            temp_dir::TempDir,
+        },
+        std::{
+            assert_matches::assert_matches,
+            ffi::CStr,
+            fs::File,
+            io::Write,
+            os::unix::io::AsFd,
         },
-        std::{assert_matches::assert_matches, ffi::CStr, os::unix::io::AsFd},
     };
 
     #[test]
@@ -183,10 +191,10 @@ mod tests
         use {CacheOutputError as Coe, OutputError as Oe};
 
         // Create state directory.
-        let path = mkdtemp(cstring!(b"/tmp/snowflake-test-XXXXXX")).unwrap();
+        let path = TempDir::new(cstring!(b"/tmp/snowflake-test-XXXXXX")).unwrap();
 
         // Create scratch directory.
-        let state = State::open(&path).unwrap();
+        let state = State::open(path.path()).unwrap();
         let scratch = state.new_scratch_dir().unwrap();
         let scratch = Some(scratch.as_fd());
 
@@ -221,4 +229,44 @@ mod tests
         test_case(&state, scratch, cstr!(b"link1"),   Oe::MULTIPLE_HARD_LINKS);
         test_case(&state, scratch, cstr!(b"link2"),   Oe::MULTIPLE_HARD_LINKS);
     }
+
+    #[test]
+    fn symbolic_links()
+    {
+        // Create state directory.
+        let path = TempDir::new(cstring!(b"/tmp/snowflake-test-XXXXXX")).unwrap();
+
+        // Create scratch directory.
+        let state = State::open(path.path()).unwrap();
+        let scratch = state.new_scratch_dir().unwrap();
+        let scratch = Some(scratch.as_fd());
+
+        // Create symbolic links, one of which is dangling,
+        // and a regular file whose contents equal a link target.
+        symlinkat(cstr!(b"target"), scratch, cstr!(b"link1")).unwrap();
+        symlinkat(cstr!(b"target"), scratch, cstr!(b"link2")).unwrap();
+        symlinkat(cstr!(b"/nonexistent"), scratch, cstr!(b"link3")).unwrap();
+        let file = openat(scratch, cstr!(b"file"), O_CREAT | O_WRONLY, 0o644).unwrap();
+        File::from(file).write_all(b"target").unwrap();
+
+        // Cache them.
+        let hash1 = state.cache_output(scratch, cstr!(b"link1")).unwrap();
+        let hash2 = state.cache_output(scratch, cstr!(b"link2")).unwrap();
+        let hash3 = state.cache_output(scratch, cstr!(b"link3")).unwrap();
+        let hash4 = state.cache_output(scratch, cstr!(b"file")).unwrap();
+
+        // Only the link target counts, and the file type is significant.
+        assert_eq!(hash1, hash2);
+        assert_ne!(hash1, hash3);
+        assert_ne!(hash1, hash4);
+
+        // Cached links are links, with their original targets.
+        for (hash, target) in [(hash1, cstr!(b"target")),
+                               (hash3, cstr!(b"/nonexistent"))] {
+            let (dirfd, path) = state.cached_output(hash).unwrap();
+            let statbuf = fstatat(Some(dirfd), &path, AT_SYMLINK_NOFOLLOW).unwrap();
+            assert_eq!(statbuf.st_mode & S_IFMT, S_IFLNK);
+            assert_eq!(readlinkat(Some(dirfd), &path).unwrap().as_c_str(), target);
+        }
+    }
 }
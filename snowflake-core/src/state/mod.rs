@@ -1,28 +1,33 @@
 //! Working with state directories.
 
-pub use self::cache_output::*;
+pub use self::{cache_output::*, remote_cache::*};
 
 use {
+    crate::action::Perform,
     os_ext::{
         AT_SYMLINK_FOLLOW,
-        O_DIRECTORY, O_PATH, O_RDONLY, O_TMPFILE, O_WRONLY,
-        cstr, linkat, mkdirat, open, openat,
+        O_DIRECTORY, O_PATH, O_RDONLY, O_RDWR, O_TMPFILE, O_WRONLY,
+        RENAME_NOREPLACE,
+        cstr, fdopendir, linkat, mkdirat, open, openat, readdir, renameat2,
         io::magic_link,
+        temp_dir::remove_all,
     },
     serde::{Deserialize, Serialize},
-    snowflake_util::hash::Hash,
+    snowflake_util::hash::{Hash, hash_file_at},
     std::{
         ffi::{CStr, CString},
         fs::File,
-        io::{self, BufReader, ErrorKind::{AlreadyExists, NotFound}, Write},
+        io::{self, BufReader, ErrorKind::{AlreadyExists, NotFound}, Read, Write},
         lazy::SyncOnceCell,
         os::unix::io::{AsFd, BorrowedFd, OwnedFd},
-        sync::atomic::{AtomicU32, Ordering::SeqCst},
+        sync::atomic::{AtomicU64, Ordering::SeqCst},
     },
+    thiserror::Error,
     uuid::Uuid,
 };
 
 mod cache_output;
+mod remote_cache;
 
 // Paths to the different components of the state directory.
 // TODO: Replace with cstr! macro once from_ptr is const.
@@ -33,6 +38,9 @@ const ACTION_CACHE_DIR: &CStr =
 const OUTPUT_CACHE_DIR: &CStr =
     unsafe { CStr::from_bytes_with_nul_unchecked(b"output-cache\0") };
 
+/// How many names to try when creating a scratch file.
+const SCRATCH_ATTEMPTS: usize = 16;
+
 /// Handle to a state directory.
 pub struct State
 {
@@ -51,13 +59,58 @@ pub struct State
     unique_id: Uuid,
 
     /// Name of the next scratch file to create.
-    next_scratch: AtomicU32,
+    ///
+    /// This is 64 bits wide so that it never wraps around,
+    /// which would cause names to be reused within one handle.
+    next_scratch: AtomicU64,
+
+    /// Cache to fall back to on local cache misses.
+    remote: Option<Box<dyn RemoteCache>>,
+
+    /// Whether to insert locally cached actions into the remote cache.
+    push_to_remote: bool,
+}
+
+/// Build log and scratch directory for performing an action.
+///
+/// Obtained from [`State::prepare_perform`].
+pub struct PreparedPerform
+{
+    /// File that will contain the build log.
+    ///
+    /// Once the action has been performed, the build log can be
+    /// inserted into the output cache using [`State::cache_build_log`].
+    pub build_log: OwnedFd,
+
+    /// Scratch directory in which the action will create its outputs.
+    pub scratch: OwnedFd,
+}
+
+impl PreparedPerform
+{
+    /// Borrow the build log and scratch directory for [`Action::perform`].
+    ///
+    /// [`Action::perform`]: `crate::action::Action::perform`
+    pub fn perform(&self) -> Perform
+    {
+        Perform{
+            build_log: self.build_log.as_fd(),
+            scratch: self.scratch.as_fd(),
+        }
+    }
 }
 
 /// Cached information about an action.
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct ActionCacheEntry
 {
+    /// The version of the format of the entry.
+    ///
+    /// Entries written before versioning was introduced lack this field,
+    /// and are read as version 0.
+    #[serde(default)]
+    pub version: u32,
+
     /// The hash of the build log.
     ///
     /// This enables finding the build log in the output cache.
@@ -72,6 +125,16 @@ pub struct ActionCacheEntry
     /// [output labels]: `crate::label::ActionOutputLabel`
     pub outputs: Vec<Hash>,
 
+    /// The hash of each input of the action.
+    ///
+    /// These are the hashes the action hash was computed from.
+    /// They are not needed for looking up the action,
+    /// but explain where a cache entry came from.
+    /// Entries of version 1 and older lack this field,
+    /// and are read with no inputs.
+    #[serde(default)]
+    pub inputs: Vec<Hash>,
+
     /// Whether warnings were emitted by the action.
     ///
     /// See the manual entry on warnings for
@@ -79,6 +142,55 @@ pub struct ActionCacheEntry
     pub warnings: bool,
 }
 
+impl ActionCacheEntry
+{
+    /// The version of entries written by this version of Snowflake.
+    pub const VERSION: u32 = 2;
+
+    /// Read an entry from its serialized form.
+    ///
+    /// Entries of older versions are upgraded to the current version.
+    /// Entries of newer versions cannot be read,
+    /// as they may contain information that would be lost.
+    pub fn from_reader(reader: impl Read)
+        -> Result<Self, ActionCacheEntryError>
+    {
+        let value: serde_json::Value = serde_json::from_reader(reader)
+            .map_err(io::Error::from)?;
+
+        let version = match value.get("version") {
+            None => 0,
+            Some(version) => version.as_u64()
+                .and_then(|v| u32::try_from(v).ok())
+                .ok_or_else(|| io::Error::other("Bad action cache entry version"))?,
+        };
+        if version > Self::VERSION {
+            return Err(ActionCacheEntryError::UnsupportedVersion(version));
+        }
+
+        let mut entry: Self = serde_json::from_value(value)
+            .map_err(io::Error::from)?;
+
+        // Version 1 only added the version field.
+        // Version 2 added the inputs field, which defaults to empty.
+        entry.version = Self::VERSION;
+
+        Ok(entry)
+    }
+}
+
+/// Error returned when reading an action cache entry.
+#[allow(missing_docs)]
+#[derive(Debug, Error)]
+pub enum ActionCacheEntryError
+{
+    #[error("{0}")]
+    Io(#[from] io::Error),
+
+    #[error("Action cache entry has unsupported version {0}")]
+    UnsupportedVersion(u32),
+}
+
 impl State
 {
     /// Open a state directory.
@@ -95,13 +207,28 @@ impl State
             scratches_dir:    SyncOnceCell::new(),
             action_cache_dir: SyncOnceCell::new(),
             output_cache_dir: SyncOnceCell::new(),
-            next_scratch:     AtomicU32::new(0),
+            next_scratch:     AtomicU64::new(0),
             unique_id:        Uuid::new_v4(),
+            remote:           None,
+            push_to_remote:   false,
         };
 
         Ok(this)
     }
 
+    /// Fall back to a remote cache on local cache misses.
+    ///
+    /// Actions found in the remote cache are inserted into the local caches.
+    /// If `push` is set, actions inserted into the local caches
+    /// are also inserted into the remote cache.
+    pub fn with_remote(mut self, remote: Box<dyn RemoteCache>, push: bool)
+        -> Self
+    {
+        self.remote = Some(remote);
+        self.push_to_remote = push;
+        self
+    }
+
     /// Handle to the scratches directory.
     ///
     /// The scratches directory contains scratch files.
@@ -119,17 +246,85 @@ impl State
         CString::new(name).unwrap()
     }
 
+    /// Create a scratch file using `create`, which is given its name.
+    ///
+    /// Names are unique to this handle, but another process could
+    /// still have created a file with the same name, however unlikely.
+    /// If `create` fails with `EEXIST`, it is retried with a new name
+    /// up to [`SCRATCH_ATTEMPTS`] times.
+    fn create_scratch(&self, mut create: impl FnMut(&CStr) -> io::Result<()>)
+        -> io::Result<CString>
+    {
+        let mut attempt = 1;
+        loop {
+            let path = self.fresh_scratch();
+            match create(&path) {
+                Err(err) if err.kind() == AlreadyExists
+                    && attempt < SCRATCH_ATTEMPTS => attempt += 1,
+                result => break result.map(|()| path),
+            }
+        }
+    }
+
     /// Create and open a new scratch directory.
     ///
     /// The scratch directory starts out empty.
     pub fn new_scratch_dir(&self) -> io::Result<OwnedFd>
     {
         let scratches_dir = self.scratches_dir()?;
-        let path = self.fresh_scratch();
-        mkdirat(Some(scratches_dir), &path, 0o755)?;
+        let path = self.create_scratch(|path| {
+            mkdirat(Some(scratches_dir), path, 0o755)
+        })?;
         openat(Some(scratches_dir), &path, O_DIRECTORY | O_PATH, 0)
     }
 
+    /// Create and open a new scratch directory for a particular build.
+    ///
+    /// The scratch directory is created inside `scratches/<build_id>`,
+    /// so that all scratch directories of a build can be removed at once
+    /// using [`clean_build_scratch`][`Self::clean_build_scratch`].
+    /// The scratch directory starts out empty.
+    pub fn new_build_scratch(&self, build_id: Uuid) -> io::Result<OwnedFd>
+    {
+        let scratches_dir = Some(self.scratches_dir()?);
+        let build_path = build_scratch_path(build_id);
+        mkdirat(scratches_dir, &build_path, 0o755)
+            .or_else(ok_if_already_exists)?;
+        let build_dir = openat(scratches_dir, &build_path, O_DIRECTORY | O_PATH, 0)?;
+        let path = self.create_scratch(|path| {
+            mkdirat(Some(build_dir.as_fd()), path, 0o755)
+        })?;
+        openat(Some(build_dir.as_fd()), &path, O_DIRECTORY | O_PATH, 0)
+    }
+
+    /// Remove all scratch directories of a build.
+    ///
+    /// Scratch directories of other builds are unaffected.
+    /// If the build has no scratch directories, this method does nothing.
+    pub fn clean_build_scratch(&self, build_id: Uuid) -> io::Result<()>
+    {
+        let scratches_dir = Some(self.scratches_dir()?);
+        match remove_all(scratches_dir, &build_scratch_path(build_id)) {
+            Err(err) if err.kind() == NotFound => Ok(()),
+            result => result,
+        }
+    }
+
+    /// Create a build log and scratch directory for performing an action.
+    ///
+    /// The build log is an anonymous file in the state directory,
+    /// so it can later be linked into the output cache.
+    /// The scratch directory is created by
+    /// [`new_build_scratch`][`Self::new_build_scratch`].
+    pub fn prepare_perform(&self, build_id: Uuid)
+        -> io::Result<PreparedPerform>
+    {
+        let flags = O_TMPFILE | O_RDWR;
+        let build_log = openat(Some(self.state_dir.as_fd()), cstr!(b"."), flags, 0o644)?;
+        let scratch = self.new_build_scratch(build_id)?;
+        Ok(PreparedPerform{build_log, scratch})
+    }
+
     /// Link a file in the scratches directory.
     ///
     /// Returns the file descriptor for the scratches directory
@@ -138,12 +333,13 @@ impl State
         -> io::Result<(BorrowedFd, CString)>
     {
         let scratches_dir = self.scratches_dir()?;
-        let path = self.fresh_scratch();
-        linkat(
-            None, &magic_link(fd),
-            Some(scratches_dir), &path,
-            AT_SYMLINK_FOLLOW,
-        )?;
+        let path = self.create_scratch(|path| {
+            linkat(
+                None, &magic_link(fd),
+                Some(scratches_dir), path,
+                AT_SYMLINK_FOLLOW,
+            )
+        })?;
         Ok((scratches_dir, path))
     }
 
@@ -157,8 +353,23 @@ impl State
     ///
     /// The entry is stored at the given action hash.
     /// If the entry already exists, nothing is changed.
+    /// If configured to, the entry and the outputs it refers to
+    /// are also inserted into the remote cache.
+    /// Failing to insert them into the remote cache is not an error,
+    /// as the entry is in the local action cache either way.
     pub fn cache_action(&self, hash: Hash, entry: &ActionCacheEntry)
         -> io::Result<()>
+    {
+        self.cache_action_local(hash, entry)?;
+        if self.push_to_remote {
+            let _ = self.push_action(hash, entry);
+        }
+        Ok(())
+    }
+
+    /// Insert an entry into the local action cache.
+    fn cache_action_local(&self, hash: Hash, entry: &ActionCacheEntry)
+        -> io::Result<()>
     {
         let cache = self.action_cache_dir()?;
 
@@ -181,10 +392,17 @@ impl State
         Ok(())
     }
 
-    /// Read an entry from the action cache.
+    /// Read an entry from the local action cache.
     ///
+    /// The remote cache is not consulted, and no caches are modified.
+    /// Use [`fetch_action`][`Self::fetch_action`] to fall back to it.
     /// If there is no entry for the given action,
     /// this method returns [`None`].
+    ///
+    /// Entries of older versions are upgraded in memory;
+    /// the entry in the action cache is left unchanged.
+    /// Entries of newer versions, as written by a newer Snowflake,
+    /// are treated as if they did not exist.
     pub fn cached_action(&self, hash: Hash)
         -> io::Result<Option<ActionCacheEntry>>
     {
@@ -194,8 +412,12 @@ impl State
             Ok(file) => {
                 let file = File::from(file);
                 let file = BufReader::new(file);
-                let entry = serde_json::from_reader(file)?;
-                Ok(Some(entry: ActionCacheEntry))
+                match ActionCacheEntry::from_reader(file) {
+                    Ok(entry) => Ok(Some(entry)),
+                    Err(ActionCacheEntryError::Io(err)) => Err(err),
+                    Err(ActionCacheEntryError::UnsupportedVersion(_)) =>
+                        Ok(None),
+                }
             },
             Err(err) if err.kind() == NotFound => Ok(None),
             Err(err) => Err(err),
@@ -261,6 +483,52 @@ impl State
         Ok((dirfd, path))
     }
 
+    /// Check that every cached output still has the hash it is named after.
+    ///
+    /// Returns the hashes of the outputs whose contents have changed,
+    /// for instance due to disk errors or meddling users.
+    /// If `repair` is set, such outputs are moved out of the output cache,
+    /// into the scratches directory.
+    /// Entries whose names are not hashes are ignored.
+    ///
+    /// Outputs are inserted into the output cache by atomic renames,
+    /// and are never modified afterwards.
+    /// So this method may run concurrently with builds,
+    /// although outputs inserted in the meantime may not be checked.
+    pub fn verify_output_cache(&self, repair: bool) -> io::Result<Vec<Hash>>
+    {
+        let output_cache_dir = self.output_cache_dir()?;
+        let dir = openat(Some(output_cache_dir), cstr!(b"."), O_DIRECTORY | O_RDONLY, 0)?;
+        let mut entries = fdopendir(dir)?;
+
+        let mut corrupted = Vec::new();
+        while let Some(entry) = readdir(&mut entries)? {
+            let expected = match path_to_hash(&entry.d_name) {
+                Some(expected) => expected,
+                None => continue,
+            };
+            let actual = hash_file_at(Some(output_cache_dir), &entry.d_name)?;
+            if actual == expected {
+                continue;
+            }
+
+            if repair {
+                let scratches_dir = self.scratches_dir()?;
+                self.create_scratch(|path| {
+                    renameat2(
+                        Some(output_cache_dir), &entry.d_name,
+                        Some(scratches_dir), path,
+                        RENAME_NOREPLACE,
+                    )
+                })?;
+            }
+
+            corrupted.push(expected);
+        }
+
+        Ok(corrupted)
+    }
+
     /// Ensure that a directory exists and open it.
     fn ensure_open_dir_once<'a>(
         &self,
@@ -292,6 +560,36 @@ fn hash_to_path(hash: &Hash) -> CString
         .expect("Hash as Display should not write nul")
 }
 
+fn build_scratch_path(build_id: Uuid) -> CString
+{
+    CString::new(build_id.to_string())
+        .expect("Uuid as Display should not write nul")
+}
+
+/// Inverse of [`hash_to_path`].
+///
+/// Returns [`None`] if the path is not the lower-case hexadecimal
+/// representation of a hash.
+fn path_to_hash(path: &CStr) -> Option<Hash>
+{
+    let path = path.to_bytes();
+    if path.len() != 64 {
+        return None;
+    }
+
+    let digit = |c: u8| match c {
+        b'0' ..= b'9' => Some(c - b'0'),
+        b'a' ..= b'f' => Some(c - b'a' + 10),
+        _ => None,
+    };
+
+    let mut hash = [0; 32];
+    for (b, pair) in hash.iter_mut().zip(path.chunks(2)) {
+        *b = digit(pair[0])? << 4 | digit(pair[1])?;
+    }
+    Some(Hash(hash))
+}
+
 fn ok_if_already_exists(err: io::Error) -> io::Result<()>
 {
     if err.kind() == AlreadyExists {
@@ -306,18 +604,27 @@ mod tests
 {
     use {
         super::*,
-        os_ext::{O_CREAT, O_WRONLY, cstr, cstring, mkdtemp, readlink},
-        std::{os::unix::io::AsFd},
+        os_ext::{
+            O_CREAT, O_WRONLY, cstr, cstring, readlink,
+            io::fd_path,
+            temp_dir::TempDir,
+        },
+        std::{
+            assert_matches::assert_matches,
+            collections::HashSet,
+            os::unix::io::AsFd,
+            thread,
+        },
     };
 
     #[test]
     fn new_scratch_dir()
     {
         // Create state directory.
-        let path = mkdtemp(cstring!(b"/tmp/snowflake-test-XXXXXX")).unwrap();
+        let path = TempDir::new(cstring!(b"/tmp/snowflake-test-XXXXXX")).unwrap();
 
         // Create two scratch directories.
-        let state = State::open(&path).unwrap();
+        let state = State::open(path.path()).unwrap();
         let scratch_dir_0 = state.new_scratch_dir().unwrap();
         let scratch_dir_1 = state.new_scratch_dir().unwrap();
 
@@ -337,18 +644,129 @@ mod tests
         ).unwrap();
     }
 
+    #[test]
+    fn new_scratch_dir_concurrent()
+    {
+        // Create state directory.
+        let path = TempDir::new(cstring!(b"/tmp/snowflake-test-XXXXXX")).unwrap();
+
+        // Create many scratch directories from two handles at once.
+        let threads: Vec<_> = (0 .. 2).map(|_| {
+            let path = path.path().to_owned();
+            thread::spawn(move || {
+                let state = State::open(&path).unwrap();
+                (0 .. 500)
+                    .map(|_| state.new_scratch_dir().unwrap())
+                    .map(|dir| fd_path(dir.as_fd()).unwrap())
+                    .collect::<Vec<_>>()
+            })
+        }).collect();
+        let paths: Vec<_> =
+            threads.into_iter()
+            .flat_map(|thread| thread.join().unwrap())
+            .collect();
+
+        // Every scratch directory must be distinct.
+        let unique: HashSet<_> = paths.iter().collect();
+        assert_eq!(unique.len(), 1000);
+    }
+
+    #[test]
+    fn new_scratch_dir_skips_existing()
+    {
+        // Create state directory.
+        let path = TempDir::new(cstring!(b"/tmp/snowflake-test-XXXXXX")).unwrap();
+        let state = State::open(path.path()).unwrap();
+
+        // Take the name of the first scratch directory.
+        let scratches_dir = Some(state.scratches_dir().unwrap());
+        let taken = format!("{}-0", state.unique_id);
+        mkdirat(scratches_dir, &CString::new(taken).unwrap(), 0o755).unwrap();
+
+        // The scratch directory must get the next name instead.
+        let scratch_dir = state.new_scratch_dir().unwrap();
+        let scratch_dir_path = fd_path(scratch_dir.as_fd()).unwrap();
+        let expected = format!("/scratches/{}-1", state.unique_id);
+        assert!(scratch_dir_path.to_bytes().ends_with(expected.as_bytes()));
+    }
+
+    #[test]
+    fn clean_build_scratch()
+    {
+        // Create state directory.
+        let path = TempDir::new(cstring!(b"/tmp/snowflake-test-XXXXXX")).unwrap();
+        let state = State::open(path.path()).unwrap();
+
+        // Create scratch directories for two builds.
+        let (build_a, build_b) = (Uuid::new_v4(), Uuid::new_v4());
+        let scratch_a = state.new_build_scratch(build_a).unwrap();
+        let scratch_b = state.new_build_scratch(build_b).unwrap();
+        for scratch in [&scratch_a, &scratch_b] {
+            let scratch = Some(scratch.as_fd());
+            mkdirat(scratch, cstr!(b"output"), 0o755).unwrap();
+            openat(scratch, cstr!(b"output/file"), O_CREAT | O_WRONLY, 0o644).unwrap();
+        }
+        let path_a = fd_path(scratch_a.as_fd()).unwrap();
+        let path_b = fd_path(scratch_b.as_fd()).unwrap();
+        let expected = format!("/scratches/{build_a}/");
+        assert!(path_a.to_str().unwrap().contains(&expected));
+
+        // Cleaning one build leaves the other alone.
+        state.clean_build_scratch(build_a).unwrap();
+        let err = open(&path_a, O_PATH, 0).unwrap_err();
+        assert_eq!(err.kind(), NotFound);
+        let file_b = CString::new([path_b.to_bytes(), b"/output/file"].concat());
+        open(&file_b.unwrap(), O_PATH, 0).unwrap();
+
+        // Cleaning a build without scratch directories does nothing.
+        state.clean_build_scratch(build_a).unwrap();
+    }
+
+    #[test]
+    fn perform_and_cache()
+    {
+        // Create state directory.
+        let path = TempDir::new(cstring!(b"/tmp/snowflake-test-XXXXXX")).unwrap();
+        let state = State::open(path.path()).unwrap();
+
+        // Write an output and a build log, like an action would.
+        let prepared = state.prepare_perform(Uuid::new_v4()).unwrap();
+        let perform = prepared.perform();
+        let scratch = Some(perform.scratch);
+        let file = openat(scratch, cstr!(b"output"), O_CREAT | O_WRONLY, 0o644).unwrap();
+        File::from(file).write_all(b"Hello!\n").unwrap();
+        File::from(perform.build_log.try_clone_to_owned().unwrap())
+            .write_all(b"Writing output\n").unwrap();
+
+        // Cache the output and the build log.
+        let output = state.cache_output(scratch, cstr!(b"output")).unwrap();
+        let build_log = state.cache_build_log(prepared.build_log).unwrap();
+
+        let read = |hash| {
+            let (dirfd, path) = state.cached_output(hash).unwrap();
+            let file = openat(Some(dirfd), &path, O_RDONLY, 0).unwrap();
+            let mut content = Vec::new();
+            File::from(file).read_to_end(&mut content).unwrap();
+            content
+        };
+        assert_eq!(read(output), b"Hello!\n");
+        assert_eq!(read(build_log), b"Writing output\n");
+    }
+
     #[test]
     fn action_cache()
     {
         // Create state directory.
-        let path = mkdtemp(cstring!(b"/tmp/snowflake-test-XXXXXX")).unwrap();
-        let state = State::open(&path).unwrap();
+        let path = TempDir::new(cstring!(b"/tmp/snowflake-test-XXXXXX")).unwrap();
+        let state = State::open(path.path()).unwrap();
 
         // Prepare action for inserting into action cache.
         let hash = Hash([0; 32]);
         let entry = ActionCacheEntry{
+            version: ActionCacheEntry::VERSION,
             build_log: Hash([1; 32]),
             outputs: vec![Hash([2; 32]), Hash([3; 32])],
+            inputs: vec![Hash([5; 32]), Hash([6; 32])],
             warnings: true,
         };
 
@@ -358,8 +776,97 @@ mod tests
 
         // Check that the entry was retrieved correctly.
         assert_eq!(format!("{entry:?}"), format!("{retrieved:?}"));
+        assert_eq!(retrieved.inputs, [Hash([5; 32]), Hash([6; 32])]);
 
         // Retrieving a non-existent action should return None.
         assert!(state.cached_action(Hash([4; 32])).unwrap().is_none());
     }
+
+    #[test]
+    fn action_cache_entry_unversioned()
+    {
+        let blob = format!(
+            r#"{{"build_log":{:?},"outputs":[{:?}],"warnings":false}}"#,
+            [1; 32], [2; 32],
+        );
+        let entry = ActionCacheEntry::from_reader(blob.as_bytes()).unwrap();
+        assert_eq!(entry.version, ActionCacheEntry::VERSION);
+        assert_eq!(entry.build_log, Hash([1; 32]));
+        assert_eq!(entry.outputs, [Hash([2; 32])]);
+        assert!(entry.inputs.is_empty());
+        assert!(!entry.warnings);
+    }
+
+    #[test]
+    fn action_cache_entry_future_version()
+    {
+        // Create state directory.
+        let path = TempDir::new(cstring!(b"/tmp/snowflake-test-XXXXXX")).unwrap();
+        let state = State::open(path.path()).unwrap();
+
+        // Write an entry as a future version of Snowflake would.
+        let version = ActionCacheEntry::VERSION + 1;
+        let blob = format!(
+            r#"{{"version":{},"build_log":{:?},"outputs":[],"new_field":true}}"#,
+            version, [1; 32],
+        );
+        let result = ActionCacheEntry::from_reader(blob.as_bytes());
+        assert_matches!(
+            result,
+            Err(ActionCacheEntryError::UnsupportedVersion(v)) if v == version
+        );
+
+        // Reading such an entry from the action cache is a miss.
+        let hash = Hash([0; 32]);
+        let cache = state.action_cache_dir().unwrap();
+        let pathname = CString::new(hash.to_string()).unwrap();
+        let file = openat(Some(cache), &pathname, O_CREAT | O_WRONLY, 0o644).unwrap();
+        File::from(file).write_all(blob.as_bytes()).unwrap();
+        assert!(state.cached_action(hash).unwrap().is_none());
+    }
+
+    #[test]
+    fn verify_output_cache()
+    {
+        // Create state directory.
+        let path = TempDir::new(cstring!(b"/tmp/snowflake-test-XXXXXX")).unwrap();
+        let state = State::open(path.path()).unwrap();
+
+        // Cache two outputs.
+        let scratch = state.new_scratch_dir().unwrap();
+        let scratch = Some(scratch.as_fd());
+        for (pathname, content) in [(cstr!(b"a"), b"good"), (cstr!(b"b"), b"fine")] {
+            let file = openat(scratch, pathname, O_CREAT | O_WRONLY, 0o644).unwrap();
+            File::from(file).write_all(content).unwrap();
+        }
+        let good = state.cache_output(scratch, cstr!(b"a")).unwrap();
+        let bad = state.cache_output(scratch, cstr!(b"b")).unwrap();
+        assert!(state.verify_output_cache(false).unwrap().is_empty());
+
+        // Corrupt one of them.
+        let (cache, pathname) = state.cached_output(bad).unwrap();
+        let file = openat(Some(cache), &pathname, O_WRONLY, 0).unwrap();
+        File::from(file).write_all(b"oops").unwrap();
+
+        // Without repair, the corrupted output stays.
+        assert_eq!(state.verify_output_cache(false).unwrap(), [bad]);
+        assert_eq!(state.verify_output_cache(true).unwrap(), [bad]);
+
+        // With repair, the corrupted output is gone.
+        assert!(state.verify_output_cache(false).unwrap().is_empty());
+        let (cache, pathname) = state.cached_output(bad).unwrap();
+        let err = openat(Some(cache), &pathname, O_PATH, 0).unwrap_err();
+        assert_eq!(err.kind(), NotFound);
+        let (cache, pathname) = state.cached_output(good).unwrap();
+        openat(Some(cache), &pathname, O_PATH, 0).unwrap();
+    }
+
+    #[test]
+    fn path_to_hash_inverse()
+    {
+        let hash = Hash([0x0F; 32]);
+        assert_eq!(path_to_hash(&hash_to_path(&hash)), Some(hash));
+        assert_eq!(path_to_hash(cstr!(b"build.log")), None);
+        assert_eq!(path_to_hash(&CString::new("G".repeat(64)).unwrap()), None);
+    }
 }
@@ -0,0 +1,343 @@
+use {
+    super::{ActionCacheEntry, CacheOutputError, State, hash_to_path},
+    os_ext::{
+        AT_SYMLINK_NOFOLLOW, O_RDWR, O_TMPFILE,
+        cstr, fstatat, openat,
+    },
+    snowflake_util::hash::{Hash, deserialize_file_at, serialize_file_at},
+    uuid::Uuid,
+    std::{
+        fs::File,
+        io::{self, BufReader, BufWriter, ErrorKind::NotFound, Read, Seek, Write},
+        iter::once,
+        os::unix::io::AsFd,
+    },
+};
+
+/* -------------------------------------------------------------------------- */
+/*                              Remote cache trait                            */
+/* -------------------------------------------------------------------------- */
+
+/// Cache shared between multiple machines.
+///
+/// A remote cache mirrors the action cache and the output cache.
+/// How entries are transferred is up to the implementation.
+/// Outputs are transferred in the format written by
+/// [`serialize_file_at`], which hashes to the hash of the output.
+/// Snowflake verifies the hashes of outputs it receives,
+/// so the remote cache need not be trusted to not corrupt them.
+/// Errors returned by the remote cache are treated as cache misses,
+/// so an unavailable remote cache does not fail the build.
+pub trait RemoteCache: Send + Sync
+{
+    /// Retrieve an entry from the remote action cache.
+    ///
+    /// If there is no entry for the given action,
+    /// this method returns [`None`].
+    fn get_action(&self, hash: Hash) -> io::Result<Option<ActionCacheEntry>>;
+
+    /// Insert an entry into the remote action cache.
+    ///
+    /// The outputs referenced by the entry are inserted beforehand.
+    fn put_action(&self, hash: Hash, entry: &ActionCacheEntry)
+        -> io::Result<()>;
+
+    /// Retrieve an output from the remote output cache.
+    ///
+    /// If there is no such output, this method returns [`None`].
+    fn get_output(&self, hash: Hash) -> io::Result<Option<Box<dyn Read>>>;
+
+    /// Insert an output into the remote output cache.
+    fn put_output(&self, hash: Hash, output: &mut dyn Read) -> io::Result<()>;
+}
+
+/* -------------------------------------------------------------------------- */
+/*                        Remote cache implementation                         */
+/* -------------------------------------------------------------------------- */
+
+impl State
+{
+    /// Read an entry from the action cache,
+    /// falling back to the remote cache on a local miss.
+    ///
+    /// If the remote cache has the action and all its outputs,
+    /// they are inserted into the local caches.
+    /// Outputs are received in scratch directories of the given build,
+    /// which are removed by [`clean_build_scratch`][`Self::clean_build_scratch`].
+    /// If any of the outputs is missing or corrupt,
+    /// or the remote cache returns an error,
+    /// this method returns [`None`].
+    pub fn fetch_action(&self, hash: Hash, build_id: Uuid)
+        -> io::Result<Option<ActionCacheEntry>>
+    {
+        if let Some(entry) = self.cached_action(hash)? {
+            return Ok(Some(entry));
+        }
+
+        let Some(remote) = &self.remote else { return Ok(None) };
+
+        let Ok(Some(entry)) = remote.get_action(hash) else { return Ok(None) };
+
+        for &output in once(&entry.build_log).chain(&entry.outputs) {
+            if !self.fetch_output(remote.as_ref(), build_id, output)? {
+                return Ok(None);
+            }
+        }
+
+        self.cache_action_local(hash, &entry)?;
+
+        Ok(Some(entry))
+    }
+
+    /// Ensure that an output is in the local output cache.
+    ///
+    /// Returns whether the output is now in the local output cache.
+    /// Only errors from the local caches are returned as errors.
+    fn fetch_output(&self, remote: &dyn RemoteCache, build_id: Uuid, hash: Hash)
+        -> io::Result<bool>
+    {
+        let output_cache_dir = self.output_cache_dir()?;
+        match fstatat(Some(output_cache_dir), &hash_to_path(&hash), AT_SYMLINK_NOFOLLOW) {
+            Ok(_) => return Ok(true),
+            Err(err) if err.kind() == NotFound => (),
+            Err(err) => return Err(err),
+        }
+
+        let Ok(Some(output)) = remote.get_output(hash) else { return Ok(false) };
+
+        // Recreate the output in a scratch directory, then cache it.
+        // A failure to read the output is a failure of the remote cache.
+        let scratch = self.new_build_scratch(build_id)?;
+        let mut output = BufReader::new(output);
+        let path = cstr!(b"output");
+        if deserialize_file_at(&mut output, Some(scratch.as_fd()), path).is_err() {
+            return Ok(false);
+        }
+
+        // Caching the output computes its hash, which must match.
+        // A mismatching output is cached under its actual hash,
+        // which does no harm as it is simply never looked up.
+        match self.cache_output(Some(scratch.as_fd()), path) {
+            Ok(actual) => Ok(actual == hash),
+            Err(CacheOutputError::Io(err)) => Err(err),
+            Err(CacheOutputError::Output(_)) => Ok(false),
+        }
+    }
+
+    /// Insert an action and its outputs into the remote cache.
+    ///
+    /// The outputs must be in the local output cache.
+    pub (super) fn push_action(&self, hash: Hash, entry: &ActionCacheEntry)
+        -> io::Result<()>
+    {
+        let Some(remote) = &self.remote else { return Ok(()) };
+
+        // Insert the outputs first, so that the remote action cache
+        // never refers to outputs missing from the remote output cache.
+        let output_cache_dir = self.output_cache_dir()?;
+        for &output in once(&entry.build_log).chain(&entry.outputs) {
+            let flags = O_TMPFILE | O_RDWR;
+            let file = openat(Some(self.scratches_dir()?), cstr!(b"."), flags, 0o644)?;
+            let mut file = File::from(file);
+
+            let mut writer = BufWriter::new(&mut file);
+            serialize_file_at(&mut writer, Some(output_cache_dir), &hash_to_path(&output))?;
+            writer.flush()?;
+            drop(writer);
+
+            file.rewind()?;
+            remote.put_output(output, &mut file)?;
+        }
+
+        remote.put_action(hash, entry)
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+/*                                    Tests                                   */
+/* -------------------------------------------------------------------------- */
+
+#[cfg(test)]
+mod tests
+{
+    use {
+        super::*,
+        os_ext::{O_CREAT, O_WRONLY, cstring, mkdirat, temp_dir::TempDir},
+        snowflake_util::hash::hash_file_at,
+        std::{
+            collections::HashMap,
+            io::{Cursor, ErrorKind::ConnectionRefused},
+            sync::{Arc, Mutex},
+        },
+    };
+
+    /// Remote cache that stores everything in memory.
+    #[derive(Clone, Default)]
+    struct MemoryRemoteCache
+    {
+        actions: Arc<Mutex<HashMap<[u8; 32], ActionCacheEntry>>>,
+        outputs: Arc<Mutex<HashMap<[u8; 32], Vec<u8>>>>,
+    }
+
+    impl RemoteCache for MemoryRemoteCache
+    {
+        fn get_action(&self, hash: Hash) -> io::Result<Option<ActionCacheEntry>>
+        {
+            Ok(self.actions.lock().unwrap().get(&hash.0).cloned())
+        }
+
+        fn put_action(&self, hash: Hash, entry: &ActionCacheEntry)
+            -> io::Result<()>
+        {
+            self.actions.lock().unwrap().insert(hash.0, entry.clone());
+            Ok(())
+        }
+
+        fn get_output(&self, hash: Hash) -> io::Result<Option<Box<dyn Read>>>
+        {
+            let outputs = self.outputs.lock().unwrap();
+            let output = outputs.get(&hash.0).cloned();
+            Ok(output.map(|o| Box::new(Cursor::new(o)) as Box<dyn Read>))
+        }
+
+        fn put_output(&self, hash: Hash, output: &mut dyn Read) -> io::Result<()>
+        {
+            let mut buf = Vec::new();
+            output.read_to_end(&mut buf)?;
+            self.outputs.lock().unwrap().insert(hash.0, buf);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn local_miss_served_from_remote()
+    {
+        let remote = MemoryRemoteCache::default();
+
+        // Build an action on one machine, which pushes it to the remote.
+        let path_a = TempDir::new(cstring!(b"/tmp/snowflake-test-XXXXXX")).unwrap();
+        let state_a = State::open(path_a.path()).unwrap()
+            .with_remote(Box::new(remote.clone()), true);
+        let scratch = state_a.new_scratch_dir().unwrap();
+        let scratch = Some(scratch.as_fd());
+        mkdirat(scratch, cstr!(b"output"), 0o755).unwrap();
+        openat(scratch, cstr!(b"output/file"), O_CREAT | O_WRONLY, 0o644).unwrap();
+        openat(scratch, cstr!(b"build.log"), O_CREAT | O_WRONLY, 0o644).unwrap();
+        let output = state_a.cache_output(scratch, cstr!(b"output")).unwrap();
+        let build_log = state_a.cache_output(scratch, cstr!(b"build.log")).unwrap();
+        let hash = Hash([0; 32]);
+        let entry = ActionCacheEntry{
+            version: ActionCacheEntry::VERSION,
+            build_log,
+            outputs: vec![output],
+            inputs: Vec::new(),
+            warnings: false,
+        };
+        state_a.cache_action(hash, &entry).unwrap();
+        assert_eq!(remote.outputs.lock().unwrap().len(), 2);
+        assert_eq!(remote.actions.lock().unwrap().len(), 1);
+
+        // Look up the action on another machine.
+        // Looking up only locally does not consult the remote cache.
+        let path_b = TempDir::new(cstring!(b"/tmp/snowflake-test-XXXXXX")).unwrap();
+        let state_b = State::open(path_b.path()).unwrap()
+            .with_remote(Box::new(remote.clone()), false);
+        assert!(state_b.cached_action(hash).unwrap().is_none());
+        let build_id = Uuid::new_v4();
+        let retrieved = state_b.fetch_action(hash, build_id).unwrap().unwrap();
+        assert_eq!(format!("{entry:?}"), format!("{retrieved:?}"));
+        state_b.clean_build_scratch(build_id).unwrap();
+
+        // The action and its outputs must have been cached locally.
+        let state_b = State::open(path_b.path()).unwrap();
+        let retrieved = state_b.cached_action(hash).unwrap().unwrap();
+        assert_eq!(format!("{entry:?}"), format!("{retrieved:?}"));
+        let (dirfd, path) = state_b.cached_output(output).unwrap();
+        assert_eq!(hash_file_at(Some(dirfd), &path).unwrap(), output);
+
+        // A miss on both ends is still a miss.
+        assert!(state_b.fetch_action(Hash([1; 32]), build_id).unwrap().is_none());
+    }
+
+    /// Remote cache that fails every request.
+    struct BrokenRemoteCache;
+
+    impl RemoteCache for BrokenRemoteCache
+    {
+        fn get_action(&self, _hash: Hash) -> io::Result<Option<ActionCacheEntry>>
+        {
+            Err(io::Error::from(ConnectionRefused))
+        }
+
+        fn put_action(&self, _hash: Hash, _entry: &ActionCacheEntry)
+            -> io::Result<()>
+        {
+            Err(io::Error::from(ConnectionRefused))
+        }
+
+        fn get_output(&self, _hash: Hash) -> io::Result<Option<Box<dyn Read>>>
+        {
+            Err(io::Error::from(ConnectionRefused))
+        }
+
+        fn put_output(&self, _hash: Hash, _output: &mut dyn Read) -> io::Result<()>
+        {
+            Err(io::Error::from(ConnectionRefused))
+        }
+    }
+
+    #[test]
+    fn remote_errors_are_misses()
+    {
+        let path = TempDir::new(cstring!(b"/tmp/snowflake-test-XXXXXX")).unwrap();
+        let state = State::open(path.path()).unwrap()
+            .with_remote(Box::new(BrokenRemoteCache), true);
+
+        // Failing to look up an action is a miss.
+        let hash = Hash([0; 32]);
+        assert!(state.fetch_action(hash, Uuid::new_v4()).unwrap().is_none());
+
+        // Failing to push an action still caches it locally.
+        let scratch = state.new_scratch_dir().unwrap();
+        let scratch = Some(scratch.as_fd());
+        openat(scratch, cstr!(b"build.log"), O_CREAT | O_WRONLY, 0o644).unwrap();
+        let build_log = state.cache_output(scratch, cstr!(b"build.log")).unwrap();
+        let entry = ActionCacheEntry{
+            version: ActionCacheEntry::VERSION,
+            build_log,
+            outputs: Vec::new(),
+            inputs: Vec::new(),
+            warnings: false,
+        };
+        state.cache_action(hash, &entry).unwrap();
+        assert!(state.cached_action(hash).unwrap().is_some());
+    }
+
+    #[test]
+    fn corrupt_remote_output_is_miss()
+    {
+        let remote = MemoryRemoteCache::default();
+        let path = TempDir::new(cstring!(b"/tmp/snowflake-test-XXXXXX")).unwrap();
+        let state = State::open(path.path()).unwrap()
+            .with_remote(Box::new(remote.clone()), false);
+
+        // The remote cache serves an output that does not match its hash.
+        let build_log = Hash([1; 32]);
+        let entry = ActionCacheEntry{
+            version: ActionCacheEntry::VERSION,
+            build_log,
+            outputs: Vec::new(),
+            inputs: Vec::new(),
+            warnings: false,
+        };
+        let hash = Hash([0; 32]);
+        remote.put_action(hash, &entry).unwrap();
+        let mut output: &[u8] = &[0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        remote.put_output(build_log, &mut output).unwrap();
+
+        let build_id = Uuid::new_v4();
+        assert!(state.fetch_action(hash, build_id).unwrap().is_none());
+        assert!(state.cached_action(hash).unwrap().is_none());
+        state.clean_build_scratch(build_id).unwrap();
+    }
+}
@@ -7,10 +7,12 @@ use {
         cstr, fdopendir, fstatat, openat, readdir, readlinkat, stat,
     },
     std::{
+        collections::HashMap,
         ffi::CStr,
         fs::File,
-        io::{self, Write, copy},
+        io::{self, ErrorKind::Unsupported, Write, copy},
         os::unix::io::{AsFd, BorrowedFd},
+        sync::Mutex,
     },
 };
 
@@ -64,9 +66,123 @@ pub fn hash_file_at_with(
     Ok(blake3.finalize())
 }
 
+/// The type of a file, as far as hashing is concerned.
+///
+/// Only regular files, directories, and symbolic links can be hashed.
+/// Code that treats files differently depending on their type
+/// should use this classification, so that it agrees with hashing.
+#[allow(missing_docs)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FileType
+{
+    Regular,
+    Directory,
+    Symlink,
+    Other,
+}
+
+impl FileType
+{
+    /// Find the type of a file from its mode.
+    pub fn from_mode(mode: u32) -> Self
+    {
+        match mode & S_IFMT {
+            S_IFREG => Self::Regular,
+            S_IFDIR => Self::Directory,
+            S_IFLNK => Self::Symlink,
+            _       => Self::Other,
+        }
+    }
+}
+
+/// Memoizes [`hash_file_at`] for regular files.
+///
+/// Regular files are identified by their device and inode numbers.
+/// Alongside the hash, the cache remembers the modification time,
+/// size, and mode of the file at the time it was hashed.
+/// If any of these changed, the file is hashed again.
+/// Directories and symbolic links are always hashed again,
+/// as their modification times do not reflect changes to nested files.
+///
+/// # Staleness
+///
+/// A file that is modified after it was hashed, but within
+/// the granularity of the file system's timestamps,
+/// is not detected as changed and its stale hash is returned.
+/// Resetting the modification time does not hide changes,
+/// as the status change time is also checked.
+/// This is unlikely for inputs, which are not written during builds,
+/// but the cache should not be used for files that may be.
+#[derive(Debug, Default)]
+pub struct FileHashCache
+{
+    entries: Mutex<HashMap<(u64, u64), (FileHashCacheStamp, Hash)>>,
+}
+
+/// What must be unchanged for a cache entry to be valid.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct FileHashCacheStamp
+{
+    mtime: (i64, i64),
+    ctime: (i64, i64),
+    size: i64,
+    mode: u32,
+}
+
+impl FileHashCache
+{
+    /// Create an empty cache.
+    pub fn new() -> Self
+    {
+        Self::default()
+    }
+
+    /// Like [`hash_file_at`], but skip hashing unchanged regular files.
+    pub fn hash(&self, dirfd: Option<BorrowedFd>, path: &CStr)
+        -> io::Result<Hash>
+    {
+        let statbuf = fstatat(dirfd, path, AT_SYMLINK_NOFOLLOW)?;
+        if FileType::from_mode(statbuf.st_mode) != FileType::Regular {
+            return hash_file_at(dirfd, path);
+        }
+
+        if let Some(hash) = self.lookup(&statbuf) {
+            return Ok(hash);
+        }
+
+        let hash = hash_file_at(dirfd, path)?;
+        let (key, stamp) = Self::key_stamp(&statbuf);
+        self.entries.lock().unwrap().insert(key, (stamp, hash));
+        Ok(hash)
+    }
+
+    /// Find the cached hash of a file, if it is still valid.
+    fn lookup(&self, statbuf: &stat) -> Option<Hash>
+    {
+        let (key, stamp) = Self::key_stamp(statbuf);
+        let entries = self.entries.lock().unwrap();
+        match entries.get(&key) {
+            Some(&(cached_stamp, hash)) if cached_stamp == stamp => Some(hash),
+            _ => None,
+        }
+    }
+
+    fn key_stamp(statbuf: &stat) -> ((u64, u64), FileHashCacheStamp)
+    {
+        let key = (statbuf.st_dev, statbuf.st_ino);
+        let stamp = FileHashCacheStamp{
+            mtime: (statbuf.st_mtime, statbuf.st_mtime_nsec),
+            ctime: (statbuf.st_ctime, statbuf.st_ctime_nsec),
+            size: statbuf.st_size,
+            mode: statbuf.st_mode,
+        };
+        (key, stamp)
+    }
+}
+
 // NOTE: See the manual chapter on avoiding hash collisions.
 
-fn write_file_at(
+pub (super) fn write_file_at(
     writer: &mut impl Write,
     dirfd:  Option<BorrowedFd>,
     path:   &CStr,
@@ -75,18 +191,18 @@ fn write_file_at(
 {
     let statbuf = fstatat(dirfd, path, AT_SYMLINK_NOFOLLOW)?;
     f(&statbuf)?;
-    match statbuf.st_mode & S_IFMT {
-        S_IFREG => write_reg_at(writer, dirfd, path, &statbuf),
-        S_IFDIR => write_dir_at(writer, dirfd, path, f),
-        S_IFLNK => write_lnk_at(writer, dirfd, path),
-        _       => todo!("Return error about unsupported file type"),
+    match FileType::from_mode(statbuf.st_mode) {
+        FileType::Regular   => write_reg_at(writer, dirfd, path, &statbuf),
+        FileType::Directory => write_dir_at(writer, dirfd, path, f),
+        FileType::Symlink   => write_lnk_at(writer, dirfd, path),
+        FileType::Other     => Err(io::Error::new(Unsupported, "Unsupported file type")),
     }
 }
 
 // Byte which indicates the type of file.
-const FILE_TYPE_REG: u8 = 0;
-const FILE_TYPE_DIR: u8 = 1;
-const FILE_TYPE_LNK: u8 = 2;
+pub (super) const FILE_TYPE_REG: u8 = 0;
+pub (super) const FILE_TYPE_DIR: u8 = 1;
+pub (super) const FILE_TYPE_LNK: u8 = 2;
 
 /// Write a regular file.
 fn write_reg_at(
@@ -181,7 +297,11 @@ fn write_lnk_at(
 #[cfg(test)]
 mod tests
 {
-    use super::*;
+    use {
+        super::*,
+        os_ext::{O_CREAT, O_WRONLY, cstring, temp_dir::TempDir},
+        std::os::unix::io::AsRawFd,
+    };
 
     #[test]
     fn example()
@@ -222,4 +342,55 @@ mod tests
         let hash = hash_file_at(None, path).unwrap();
         assert_eq!(hash, expected_hash);
     }
+
+    #[test]
+    fn file_hash_cache()
+    {
+        let temp_dir = TempDir::new(cstring!(b"/tmp/snowflake-test-XXXXXX")).unwrap();
+        let dirfd = Some(temp_dir.as_fd());
+        let path = cstr!(b"file");
+        let write = |content: &[u8]| {
+            let file = openat(dirfd, path, O_CREAT | O_WRONLY, 0o644).unwrap();
+            File::from(file).write_all(content).unwrap();
+        };
+        let lookup = |cache: &FileHashCache| {
+            let statbuf = fstatat(dirfd, path, AT_SYMLINK_NOFOLLOW).unwrap();
+            cache.lookup(&statbuf)
+        };
+
+        let cache = FileHashCache::new();
+        write(b"hello");
+        assert_eq!(lookup(&cache), None);
+
+        // Hashing the file caches its hash.
+        let hash_0 = cache.hash(dirfd, path).unwrap();
+        assert_eq!(hash_0, hash_file_at(dirfd, path).unwrap());
+        assert_eq!(lookup(&cache), Some(hash_0));
+        assert_eq!(cache.hash(dirfd, path).unwrap(), hash_0);
+
+        // Changing the file invalidates the cached hash.
+        write(b"hello, world");
+        assert_eq!(lookup(&cache), None);
+        let hash_1 = cache.hash(dirfd, path).unwrap();
+        assert_ne!(hash_1, hash_0);
+        assert_eq!(hash_1, hash_file_at(dirfd, path).unwrap());
+        assert_eq!(lookup(&cache), Some(hash_1));
+
+        // Resetting the modification time does not hide changes.
+        let before = fstatat(dirfd, path, AT_SYMLINK_NOFOLLOW).unwrap();
+        write(b"HELLO, WORLD");
+        let times = [
+            libc::timespec{tv_sec: before.st_atime, tv_nsec: before.st_atime_nsec},
+            libc::timespec{tv_sec: before.st_mtime, tv_nsec: before.st_mtime_nsec},
+        ];
+        // SAFETY: path is nul-terminated and times has two elements.
+        let result = unsafe {
+            libc::utimensat(temp_dir.as_fd().as_raw_fd(), path.as_ptr(),
+                            times.as_ptr(), libc::AT_SYMLINK_NOFOLLOW)
+        };
+        assert_eq!(result, 0);
+        let after = fstatat(dirfd, path, AT_SYMLINK_NOFOLLOW).unwrap();
+        assert_eq!(after.st_mtime_nsec, before.st_mtime_nsec);
+        assert_eq!(lookup(&cache), None);
+    }
 }
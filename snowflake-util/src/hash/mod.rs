@@ -1,12 +1,13 @@
 //! Identifying elements of a cache.
 
-pub use self::{blake3::*, file::*};
+pub use self::{blake3::*, file::*, serialize::*};
 
 use {serde::{Deserialize, Serialize}, std::{fmt, str::from_utf8_unchecked}};
 
 mod blake3;
 mod file;
 mod put;
+mod serialize;
 
 /// Cryptographic hash used for identifying elements of a cache.
 ///
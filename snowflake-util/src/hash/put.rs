@@ -1,4 +1,7 @@
-use {super::{Blake3, Hash}, std::ffi::CStr};
+use {
+    super::{Blake3, Hash},
+    std::{ffi::CStr, os::unix::ffi::OsStrExt, path::Path},
+};
 
 /// Convenient methods for writing values.
 ///
@@ -54,6 +57,11 @@ impl Blake3
         self.update(value.to_bytes_with_nul())
     }
 
+    pub fn put_path(&mut self, value: &Path) -> &mut Self
+    {
+        self.put_bytes(value.as_os_str().as_bytes())
+    }
+
     pub fn put_slice<F, T>(&mut self, value: &[T], mut f: F) -> &mut Self
         where F: for<'a> FnMut(&'a mut Self, &T) -> &'a mut Self
     {
@@ -63,4 +71,86 @@ impl Blake3
         }
         self
     }
+
+    /// Like [`put_slice`][`Self::put_slice`], but order-insensitive.
+    ///
+    /// The entries are written in ascending order of `key`,
+    /// so slices that are permutations of each other hash the same.
+    /// Entries with equal keys keep their relative order.
+    pub fn put_map<'v, T, K, KF, VF>(&mut self, value: &'v [T], mut key: KF,
+                                     mut f: VF) -> &mut Self
+        where KF: FnMut(&'v T) -> K
+            , VF: for<'a> FnMut(&'a mut Self, &T) -> &'a mut Self
+            , K: Ord
+    {
+        let mut sorted: Vec<&T> = value.iter().collect();
+        sorted.sort_by_key(|value| key(value));
+        self.put_usize(sorted.len());
+        for value in sorted {
+            f(self, value);
+        }
+        self
+    }
+
+    pub fn put_opt<F, T>(&mut self, value: Option<&T>, f: F) -> &mut Self
+        where F: for<'a> FnOnce(&'a mut Self, &T) -> &'a mut Self
+            , T: ?Sized
+    {
+        self.put_bool(value.is_some());
+        if let Some(value) = value {
+            f(self, value);
+        }
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use {super::*, os_ext::cstr};
+
+    #[test]
+    fn chained_equals_unchained()
+    {
+        let mut unchained = Blake3::new();
+        unchained.put_u8(1);
+        unchained.put_str("foo");
+        unchained.put_cstr(cstr!(b"bar"));
+        unchained.put_slice(&[1, 2], |h, &v| h.put_u64(v));
+
+        let chained =
+            Blake3::new()
+            .put_u8(1)
+            .put_str("foo")
+            .put_cstr(cstr!(b"bar"))
+            .put_slice(&[1, 2], |h, &v| h.put_u64(v))
+            .finalize();
+
+        assert_eq!(chained, unchained.finalize());
+    }
+
+    #[test]
+    fn put_map_order_insensitive()
+    {
+        fn key(&(k, _): &(u8, u8)) -> u8 { k }
+        fn put<'a>(h: &'a mut Blake3, &(k, v): &(u8, u8)) -> &'a mut Blake3
+        {
+            h.put_u8(k).put_u8(v)
+        }
+
+        let a = Blake3::new().put_map(&[(1, 2), (3, 4)], key, put).finalize();
+        let b = Blake3::new().put_map(&[(3, 4), (1, 2)], key, put).finalize();
+        let c = Blake3::new().put_map(&[(1, 4), (3, 2)], key, put).finalize();
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+    }
+
+    #[test]
+    fn put_opt_encoding()
+    {
+        let some = Blake3::new().put_opt(Some("foo"), Blake3::put_str).finalize();
+        let none = Blake3::new().put_opt(None, Blake3::put_str).finalize();
+        assert_eq!(some, Blake3::new().put_bool(true).put_str("foo").finalize());
+        assert_eq!(none, Blake3::new().put_bool(false).finalize());
+    }
 }
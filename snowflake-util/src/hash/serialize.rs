@@ -0,0 +1,187 @@
+use {
+    super::file::{FILE_TYPE_DIR, FILE_TYPE_LNK, FILE_TYPE_REG, write_file_at},
+    os_ext::{
+        O_CREAT, O_DIRECTORY, O_EXCL, O_NOFOLLOW, O_PATH, O_WRONLY,
+        cstr, fchmodat, mkdirat, openat, symlinkat,
+    },
+    std::{
+        ffi::{CStr, CString},
+        fs::File,
+        io::{self, BufRead, ErrorKind::{InvalidData, UnexpectedEof}, Read, Write, copy},
+        os::unix::io::{AsFd, BorrowedFd},
+    },
+};
+
+/// Write a file in the format that [`hash_file_at`][`super::hash_file_at`] hashes.
+///
+/// The format can be parsed to reconstruct the file,
+/// which is done by [`deserialize_file_at`].
+/// Hashing the written bytes with [`Blake3`][`super::Blake3`]
+/// yields the hash of the file,
+/// so the receiving end can verify what it received.
+pub fn serialize_file_at(
+    writer: &mut impl Write,
+    dirfd:  Option<BorrowedFd>,
+    path:   &CStr,
+) -> io::Result<()>
+{
+    write_file_at(writer, dirfd, path, &mut |_| Ok(()))
+}
+
+/// Create a file from the output of [`serialize_file_at`].
+///
+/// There must not already be a file at the given path.
+/// Regular files are created with permissions 755 or 644,
+/// and directories are created with permissions 755.
+/// Malformed input, including directory entry names
+/// that would escape the directory, results in an error.
+/// Files created before the error was detected are not removed.
+pub fn deserialize_file_at(
+    reader: &mut impl BufRead,
+    dirfd:  Option<BorrowedFd>,
+    path:   &CStr,
+) -> io::Result<()>
+{
+    read_file_at(reader, dirfd, path)
+}
+
+// NOTE: See the manual chapter on avoiding hash collisions.
+
+fn read_file_at(
+    reader: &mut dyn BufRead,
+    dirfd:  Option<BorrowedFd>,
+    path:   &CStr,
+) -> io::Result<()>
+{
+    let mut file_type = 0;
+    reader.read_exact(std::slice::from_mut(&mut file_type))?;
+    match file_type {
+        FILE_TYPE_REG => read_reg_at(reader, dirfd, path),
+        FILE_TYPE_DIR => read_dir_at(reader, dirfd, path),
+        FILE_TYPE_LNK => read_lnk_at(reader, dirfd, path),
+        _ => Err(io::Error::new(InvalidData, "Unknown file type")),
+    }
+}
+
+/// Read a regular file.
+fn read_reg_at(
+    reader: &mut dyn BufRead,
+    dirfd:  Option<BorrowedFd>,
+    path:   &CStr,
+) -> io::Result<()>
+{
+    // Read whether file is executable.
+    let mut executable = 0;
+    reader.read_exact(std::slice::from_mut(&mut executable))?;
+    let mode = match executable {
+        0 => 0o644,
+        1 => 0o755,
+        _ => return Err(io::Error::new(InvalidData, "Bad executable flag")),
+    };
+
+    // Read file size.
+    let mut size = [0; 8];
+    reader.read_exact(&mut size)?;
+    let size = u64::from_le_bytes(size);
+
+    // Read file contents.
+    let flags = O_CREAT | O_EXCL | O_NOFOLLOW | O_WRONLY;
+    let file = openat(dirfd, path, flags, mode)?;
+    let mut file = File::from(file);
+    let copied = copy(&mut Read::take(reader, size), &mut file)?;
+    if copied != size {
+        return Err(io::Error::from(UnexpectedEof));
+    }
+
+    // The mode passed to openat is subject to the umask.
+    fchmodat(dirfd, path, mode, 0)
+}
+
+/// Read a directory.
+fn read_dir_at(
+    reader: &mut dyn BufRead,
+    dirfd:  Option<BorrowedFd>,
+    path:   &CStr,
+) -> io::Result<()>
+{
+    mkdirat(dirfd, path, 0o755)?;
+    fchmodat(dirfd, path, 0o755, 0)?;
+    let dir = openat(dirfd, path, O_DIRECTORY | O_NOFOLLOW | O_PATH, 0)?;
+
+    // Read entries until the terminator.
+    loop {
+        let entry = read_cstring(reader)?;
+        if entry.as_bytes().is_empty() {
+            break Ok(());
+        }
+        if entry.as_bytes().contains(&b'/') ||
+            entry.as_ref() == cstr!(b".") ||
+            entry.as_ref() == cstr!(b"..") {
+            break Err(io::Error::new(InvalidData, "Bad directory entry name"));
+        }
+        read_file_at(reader, Some(dir.as_fd()), &entry)?;
+    }
+}
+
+/// Read a symbolic link.
+fn read_lnk_at(
+    reader: &mut dyn BufRead,
+    dirfd:  Option<BorrowedFd>,
+    path:   &CStr,
+) -> io::Result<()>
+{
+    let target = read_cstring(reader)?;
+    symlinkat(&target, dirfd, path)
+}
+
+/// Read a nul-terminated string.
+fn read_cstring(reader: &mut dyn BufRead) -> io::Result<CString>
+{
+    let mut buf = Vec::new();
+    reader.read_until(0, &mut buf)?;
+    if buf.pop() != Some(0) {
+        return Err(io::Error::from(UnexpectedEof));
+    }
+    // The only nul byte was the one just popped.
+    Ok(CString::new(buf).unwrap())
+}
+
+#[cfg(test)]
+mod tests
+{
+    use {
+        super::*,
+        crate::hash::{Blake3, hash_file_at},
+        os_ext::{cstring, temp_dir::TempDir},
+    };
+
+    #[test]
+    fn serialize_roundtrip()
+    {
+        let path = cstr!(b"testdata/hash_file_at");
+
+        let mut buf = Vec::new();
+        serialize_file_at(&mut buf, None, path).unwrap();
+
+        let temp_dir = TempDir::new(cstring!(b"/tmp/snowflake-test-XXXXXX")).unwrap();
+        let temp_dir = Some(temp_dir.as_fd());
+        deserialize_file_at(&mut &buf[..], temp_dir, cstr!(b"copy")).unwrap();
+
+        let expected = hash_file_at(None, path).unwrap();
+        let actual = hash_file_at(temp_dir, cstr!(b"copy")).unwrap();
+        assert_eq!(actual, expected);
+        assert_eq!(Blake3::new().update(&buf).finalize(), expected);
+    }
+
+    #[test]
+    fn deserialize_bad_entry_name()
+    {
+        let temp_dir = TempDir::new(cstring!(b"/tmp/snowflake-test-XXXXXX")).unwrap();
+        for (i, name) in [&b".."[..], b".", b"a/b"].into_iter().enumerate() {
+            let buf = [&[FILE_TYPE_DIR], name, &[0, FILE_TYPE_LNK, b'x', 0, 0]].concat();
+            let path = CString::new(i.to_string()).unwrap();
+            let result = deserialize_file_at(&mut &buf[..], Some(temp_dir.as_fd()), &path);
+            assert_eq!(result.unwrap_err().kind(), InvalidData);
+        }
+    }
+}
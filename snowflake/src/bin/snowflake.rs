@@ -43,15 +43,16 @@ fn main()
                         outputs: Outputs::Outputs(vec![
                             Basename::new(cstring!(b"stylesheet.css")).unwrap(),
                         ]),
-                        program: sassc,
-                        arguments: vec![
-                            cstring!(b"sassc"),
-                            cstring!(b"stylesheet.scss"),
-                            cstring!(b"stylesheet.css"),
-                        ],
-                        environment: vec![],
                         timeout: Duration::from_secs(1),
                         warnings: Some(Regex::new("^WARNING:").unwrap()),
+                        ..RunCommand::new(
+                            sassc,
+                            vec![
+                                cstring!(b"sassc"),
+                                cstring!(b"stylesheet.scss"),
+                                cstring!(b"stylesheet.css"),
+                            ],
+                        )
                     }) as Box<dyn Action>,
                     vec![
                         Input::StaticFile(cstring!(b"snowflake-website/stylesheet.scss")),
@@ -69,17 +70,18 @@ fn main()
                         outputs: Outputs::Outputs(vec![
                             Basename::new(cstring!(b"index.html")).unwrap(),
                         ]),
-                        program: cstring!(b"/bin/sh"),
-                        arguments: vec![
-                            cstring!(b"bash"),
-                            cstring!(b"-c"),
-                            cstring!(br#"exec m4 -P index.html.m4 > index.html"#),
-                        ],
                         environment: vec![
                             gnum4_path,
                         ],
                         timeout: Duration::from_secs(1),
-                        warnings: None,
+                        ..RunCommand::new(
+                            cstring!(b"/bin/sh"),
+                            vec![
+                                cstring!(b"bash"),
+                                cstring!(b"-c"),
+                                cstring!(br#"exec m4 -P index.html.m4 > index.html"#),
+                            ],
+                        )
                     }) as Box<dyn Action>,
                     vec![
                         Input::StaticFile(cstring!(b"snowflake-website/index.html")),
@@ -97,16 +99,16 @@ fn main()
                         outputs: Outputs::Outputs(vec![
                             Basename::new(cstring!(b"index.min.html")).unwrap(),
                         ]),
-                        program: minify,
-                        arguments: vec![
-                            cstring!(b"minify"),
-                            cstring!(b"--output"),
-                            cstring!(b"index.min.html"),
-                            cstring!(b"index.html"),
-                        ],
-                        environment: vec![],
                         timeout: Duration::from_secs(1),
-                        warnings: None,
+                        ..RunCommand::new(
+                            minify,
+                            vec![
+                                cstring!(b"minify"),
+                                cstring!(b"--output"),
+                                cstring!(b"index.min.html"),
+                                cstring!(b"index.html"),
+                            ],
+                        )
                     }) as Box<dyn Action>,
                     vec![
                         Input::Dependency(action_inject_css_output_html),
@@ -126,7 +128,7 @@ fn main()
     let state = State::open(cstr!(b".snowflake")).unwrap();
     let source_root = open(cstr!(b"."), O_DIRECTORY | O_PATH, 0).unwrap();
     let context = drive::Context{state: &state, source_root: source_root.as_fd()};
-    let result = drive(&context, &action_graph);
+    let result = drive(&context, &action_graph, &());
 
     println!("{}", action_graph);
     println!("{:#?}", result);